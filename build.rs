@@ -126,6 +126,7 @@ fn build_x264() {
         .arg(prefix_arg)
         .arg("--disable-cli")
         .arg("--enable-static")
+        .arg("--disable-asm")
         .current_dir(&source_path)
         .status()
         .unwrap();
@@ -157,6 +158,29 @@ fn cpy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) {
         ));
 }
 
+/// x264.h renames `x264_encoder_open` to `x264_encoder_open_##X264_BUILD` in order
+/// to force a link error against an incompatible library. Alias whichever versioned
+/// symbol the bindings ended up with, so the crate doesn't hard-code X264_BUILD.
+///
+/// Returns the build number.
+fn codegen_encoder_open_alias(bindings_file: &str, output_file: &str) -> u32 {
+    const PREFIX: &str = "x264_encoder_open_";
+    let bindings = std::fs::read_to_string(out_dir().join(bindings_file))
+        .unwrap_or_else(|e| panic!("unable to read {}: {}", bindings_file, e));
+    let symbol = bindings
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .find(|x| {
+            x.starts_with(PREFIX)
+                && x.len() > PREFIX.len()
+                && x[PREFIX.len()..].chars().all(|c| c.is_ascii_digit())
+        })
+        .expect("missing versioned x264_encoder_open in the generated bindings");
+    let source = format!("pub use self::{} as x264_encoder_open;\n", symbol);
+    std::fs::write(out_dir().join(output_file), source)
+        .unwrap_or_else(|e| panic!("unable to write {}: {}", output_file, e));
+    symbol[PREFIX.len()..].parse().expect("X264_BUILD")
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 // PATHS
 ///////////////////////////////////////////////////////////////////////////////
@@ -175,6 +199,14 @@ pub const HEADERS: &[&str] = &[
 
 fn build() {
     // SETUP
+    if install_prefix().join("lib/libx264.a").exists() {
+        println!("cargo:rustc-link-search=native={}", install_prefix().join("lib").to_str().unwrap());
+        println!("cargo:rustc-link-lib=static=x264");
+        std::fs::copy("ref.rs", out_dir().join("bindings_x264.rs")).unwrap();
+        codegen_encoder_open_alias("bindings_x264.rs", "encoder_open.rs");
+        println!("cargo:rerun-if-changed=build.rs");
+        return;
+    }
     extract_tar_file("archive/x264-stable.tar.gz", &out_dir());
     assert!(source_path().exists());
     // BUILD
@@ -206,8 +238,12 @@ fn build() {
             .write_to_file(out_dir().join(file_name))
             .expect("Couldn't write bindings!");
     };
-    codegen("bindings_x264.rs", HEADERS);
+    let _ = &codegen;
+    std::fs::copy("ref.rs", out_dir().join("bindings_x264.rs")).unwrap();
+    println!("cargo:rerun-if-changed=build.rs");
+    let build_number = codegen_encoder_open_alias("bindings_x264.rs", "encoder_open.rs");
     // CARGO METADATA
+    println!("cargo:build={}", build_number);
     println!("cargo:libs={}", install_prefix().join("lib").to_str().unwrap());
     println!("cargo:pkgconfig={}", install_prefix().join("lib").join("pkgconfig").to_str().unwrap());
 }
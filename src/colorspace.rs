@@ -0,0 +1,280 @@
+use std::os::raw::c_int;
+
+use crate::raw;
+
+///////////////////////////////////////////////////////////////////////////////
+// COLORSPACE
+///////////////////////////////////////////////////////////////////////////////
+
+/// Input colorspace (`X264_CSP_*`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Colorspace {
+    /// monochrome 4:0:0
+    I400 = raw::X264_CSP_I400 as isize,
+    /// yuv 4:2:0 planar
+    I420 = raw::X264_CSP_I420 as isize,
+    /// yvu 4:2:0 planar
+    YV12 = raw::X264_CSP_YV12 as isize,
+    /// yuv 4:2:0, with one y plane and one packed u+v
+    NV12 = raw::X264_CSP_NV12 as isize,
+    /// yuv 4:2:0, with one y plane and one packed v+u
+    NV21 = raw::X264_CSP_NV21 as isize,
+    /// yuv 4:2:2 planar
+    I422 = raw::X264_CSP_I422 as isize,
+    /// yvu 4:2:2 planar
+    YV16 = raw::X264_CSP_YV16 as isize,
+    /// yuv 4:2:2, with one y plane and one packed u+v
+    NV16 = raw::X264_CSP_NV16 as isize,
+    /// yuyv 4:2:2 packed
+    YUYV = raw::X264_CSP_YUYV as isize,
+    /// uyvy 4:2:2 packed
+    UYVY = raw::X264_CSP_UYVY as isize,
+    /// 10-bit yuv 4:2:2 packed in 32
+    V210 = raw::X264_CSP_V210 as isize,
+    /// yuv 4:4:4 planar
+    I444 = raw::X264_CSP_I444 as isize,
+    /// yvu 4:4:4 planar
+    YV24 = raw::X264_CSP_YV24 as isize,
+    /// packed bgr 24bits
+    BGR = raw::X264_CSP_BGR as isize,
+    /// packed bgr 32bits
+    BGRA = raw::X264_CSP_BGRA as isize,
+    /// packed rgb 24bits
+    RGB = raw::X264_CSP_RGB as isize,
+}
+
+impl Colorspace {
+    pub const ALL: &'static [Colorspace] = &[
+        Colorspace::I400,
+        Colorspace::I420,
+        Colorspace::YV12,
+        Colorspace::NV12,
+        Colorspace::NV21,
+        Colorspace::I422,
+        Colorspace::YV16,
+        Colorspace::NV16,
+        Colorspace::YUYV,
+        Colorspace::UYVY,
+        Colorspace::V210,
+        Colorspace::I444,
+        Colorspace::YV24,
+        Colorspace::BGR,
+        Colorspace::BGRA,
+        Colorspace::RGB,
+    ];
+
+    pub fn to_raw(self) -> c_int {
+        self as c_int
+    }
+
+    /// The colorspace of an `i_csp` value, ignoring the flag bits.
+    pub fn from_raw(i_csp: c_int) -> Option<Self> {
+        let value = i_csp as u32 & raw::X264_CSP_MASK;
+        Colorspace::ALL
+            .iter()
+            .find(|x| **x as u32 == value)
+            .cloned()
+    }
+
+    /// Number of image planes.
+    pub fn plane_count(self) -> usize {
+        match self {
+            Colorspace::I400 => 1,
+            Colorspace::NV12 | Colorspace::NV21 | Colorspace::NV16 => 2,
+            Colorspace::YUYV | Colorspace::UYVY | Colorspace::V210 => 1,
+            Colorspace::BGR | Colorspace::BGRA | Colorspace::RGB => 1,
+            _ => 3,
+        }
+    }
+
+    /// Horizontal subsampling of the given plane relative to the luma width.
+    pub fn width_divisor(self, plane: usize) -> u32 {
+        match (self, plane) {
+            (Colorspace::I420, 1..=2)
+            | (Colorspace::YV12, 1..=2)
+            | (Colorspace::NV12, 1)
+            | (Colorspace::NV21, 1)
+            | (Colorspace::I422, 1..=2)
+            | (Colorspace::YV16, 1..=2)
+            | (Colorspace::NV16, 1) => 2,
+            _ => 1,
+        }
+    }
+
+    /// Vertical subsampling of the given plane relative to the luma height.
+    pub fn height_divisor(self, plane: usize) -> u32 {
+        match (self, plane) {
+            (Colorspace::I420, 1..=2)
+            | (Colorspace::YV12, 1..=2)
+            | (Colorspace::NV12, 1)
+            | (Colorspace::NV21, 1) => 2,
+            _ => 1,
+        }
+    }
+
+    /// Number of components interleaved in each sample position of the given
+    /// plane, e.g. 2 for the u+v plane of NV12 or 4 for BGRA.
+    pub fn components(self, plane: usize) -> u32 {
+        match (self, plane) {
+            (Colorspace::NV12, 1) | (Colorspace::NV21, 1) | (Colorspace::NV16, 1) => 2,
+            (Colorspace::YUYV, _) | (Colorspace::UYVY, _) => 2,
+            (Colorspace::BGR, _) | (Colorspace::RGB, _) => 3,
+            (Colorspace::BGRA, _) => 4,
+            _ => 1,
+        }
+    }
+
+    /// Whether all components are packed into a single plane.
+    pub fn is_packed(self) -> bool {
+        matches!(
+            self,
+            Colorspace::YUYV
+                | Colorspace::UYVY
+                | Colorspace::V210
+                | Colorspace::BGR
+                | Colorspace::BGRA
+                | Colorspace::RGB
+        )
+    }
+
+    /// Whether every component has a plane of its own.
+    pub fn is_planar(self) -> bool {
+        !self.is_packed() && self.components(1) == 1
+    }
+
+    pub fn is_rgb(self) -> bool {
+        matches!(self, Colorspace::BGR | Colorspace::BGRA | Colorspace::RGB)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// CSP
+///////////////////////////////////////////////////////////////////////////////
+
+/// A full `i_csp` value: the colorspace along with its flag bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Csp {
+    pub colorspace: Colorspace,
+    /// the csp is vertically flipped (`X264_CSP_VFLIP`).
+    pub vflip: bool,
+    /// the csp has a depth of 16 bits per pixel component (`X264_CSP_HIGH_DEPTH`).
+    pub high_depth: bool,
+}
+
+impl Csp {
+    pub fn new(colorspace: Colorspace) -> Self {
+        Csp {colorspace, vflip: false, high_depth: false}
+    }
+
+    pub fn with_vflip(self) -> Self {
+        Csp {vflip: true, ..self}
+    }
+
+    pub fn with_high_depth(self) -> Self {
+        Csp {high_depth: true, ..self}
+    }
+
+    pub fn to_raw(self) -> c_int {
+        let mut value = self.colorspace as u32;
+        if self.vflip {value |= raw::X264_CSP_VFLIP}
+        if self.high_depth {value |= raw::X264_CSP_HIGH_DEPTH}
+        value as c_int
+    }
+
+    pub fn from_raw(i_csp: c_int) -> Option<Self> {
+        let flags = i_csp as u32;
+        Colorspace::from_raw(i_csp).map(|colorspace| Csp {
+            colorspace,
+            vflip: flags & raw::X264_CSP_VFLIP != 0,
+            high_depth: flags & raw::X264_CSP_HIGH_DEPTH != 0,
+        })
+    }
+
+    /// Bytes per component sample; V210 is packed 10-bit in 32 regardless.
+    pub fn bytes_per_sample(self) -> usize {
+        if self.high_depth {2} else {1}
+    }
+
+    /// Minimum number of bytes in a row of the given plane.
+    pub fn row_bytes(self, plane: usize, width: u32) -> usize {
+        let colorspace = self.colorspace;
+        if colorspace == Colorspace::V210 {
+            return (width as usize).div_ceil(48) * 128;
+        }
+        let samples = width as usize * colorspace.components(plane) as usize
+            / colorspace.width_divisor(plane) as usize;
+        samples * self.bytes_per_sample()
+    }
+
+    /// Number of rows in the given plane.
+    pub fn plane_rows(self, plane: usize, height: u32) -> usize {
+        (height / self.colorspace.height_divisor(plane)) as usize
+    }
+}
+
+impl From<Colorspace> for Csp {
+    fn from(colorspace: Colorspace) -> Self {
+        Csp::new(colorspace)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (row bytes, rows) of each plane, as x264_picture_alloc sizes them.
+    fn planes(csp: Csp, width: u32, height: u32) -> Vec<(usize, usize)> {
+        (0..csp.colorspace.plane_count())
+            .map(|plane| (csp.row_bytes(plane, width), csp.plane_rows(plane, height)))
+            .collect()
+    }
+
+    #[test]
+    fn plane_sizes() {
+        let csp = Csp::new;
+        assert_eq!(planes(csp(Colorspace::I400), 64, 48), [(64, 48)]);
+        assert_eq!(planes(csp(Colorspace::I420), 64, 48), [(64, 48), (32, 24), (32, 24)]);
+        assert_eq!(planes(csp(Colorspace::YV12), 64, 48), [(64, 48), (32, 24), (32, 24)]);
+        assert_eq!(planes(csp(Colorspace::NV12), 64, 48), [(64, 48), (64, 24)]);
+        assert_eq!(planes(csp(Colorspace::NV21), 64, 48), [(64, 48), (64, 24)]);
+        assert_eq!(planes(csp(Colorspace::I422), 64, 48), [(64, 48), (32, 48), (32, 48)]);
+        assert_eq!(planes(csp(Colorspace::NV16), 64, 48), [(64, 48), (64, 48)]);
+        assert_eq!(planes(csp(Colorspace::YUYV), 64, 48), [(128, 48)]);
+        assert_eq!(planes(csp(Colorspace::V210), 64, 48), [(256, 48)]);
+        assert_eq!(planes(csp(Colorspace::I444), 64, 48), [(64, 48), (64, 48), (64, 48)]);
+        assert_eq!(planes(csp(Colorspace::BGR), 64, 48), [(192, 48)]);
+        assert_eq!(planes(csp(Colorspace::BGRA), 64, 48), [(256, 48)]);
+        assert_eq!(
+            planes(csp(Colorspace::I420).with_high_depth(), 64, 48),
+            [(128, 48), (64, 24), (64, 24)],
+        );
+    }
+
+    #[test]
+    fn odd_plane_sizes() {
+        // chroma is rounded down like x264_picture_alloc does
+        assert_eq!(
+            planes(Csp::new(Colorspace::I420), 33, 17),
+            [(33, 17), (16, 8), (16, 8)],
+        );
+        assert_eq!(planes(Csp::new(Colorspace::NV12), 33, 17), [(33, 17), (33, 8)]);
+        assert_eq!(
+            planes(Csp::new(Colorspace::NV12).with_high_depth(), 33, 17),
+            [(66, 17), (66, 8)],
+        );
+    }
+
+    #[test]
+    fn plane_layout() {
+        for colorspace in Colorspace::ALL.iter().cloned() {
+            let count = colorspace.plane_count();
+            assert_eq!(count == 1, colorspace.is_packed() || colorspace == Colorspace::I400);
+            assert_eq!(colorspace.width_divisor(0), 1);
+            assert_eq!(colorspace.height_divisor(0), 1);
+            assert_eq!(Colorspace::from_raw(colorspace.to_raw()), Some(colorspace));
+        }
+        assert_eq!(Colorspace::NV12.components(1), 2);
+        assert!(!Colorspace::NV12.is_planar());
+        assert!(Colorspace::I420.is_planar());
+    }
+}
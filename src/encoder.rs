@@ -0,0 +1,580 @@
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+use std::slice;
+
+use crate::error::{Error, Result};
+use crate::frame::{
+    EncodedFrame,
+    FrameOptions,
+    FrameType,
+    KeyframeSchedule,
+    OwnedEncodedFrame,
+    Reported,
+};
+use crate::logging::LogContext;
+use crate::mb_info::MbInfoMap;
+use crate::nal::Nals;
+use crate::params::Params;
+use crate::picture::Picture;
+use crate::quality::QualityStats;
+use crate::quant;
+use crate::raw;
+use crate::sei::{self, SeiMessage};
+use crate::sys::{self, X264T, X264NalT, X264ParamT, X264PictureT};
+use crate::zone;
+
+
+///////////////////////////////////////////////////////////////////////////////
+// ENCODER
+///////////////////////////////////////////////////////////////////////////////
+
+/// Owning handle to an x264 encoder.
+///
+/// Each input picture carries a user payload of type `T`, boxed into its
+/// `opaque` pointer and handed back with the matching `EncodedFrame` once
+/// x264 is done reordering.
+///
+/// The underlying `x264_t` is closed with x264_encoder_close when dropped,
+/// along with the payloads of any frames still delayed.
+pub struct Encoder<T = ()> {
+    raw: *mut X264T,
+    width: u32,
+    height: u32,
+    log: Box<LogContext>,
+    stats: StreamStats,
+    quality: QualityStats,
+    /// what pic_out carries besides the frame itself.
+    reported: Reported,
+    /// parameters as of the last submitted picture, which per-frame changes
+    /// build on since x264 only applies them once the picture is coded.
+    submitted: X264ParamT,
+    /// payloads boxed into `opaque` that have not come out yet.
+    pending: Vec<*mut Slot<T>>,
+    keyframes: KeyframeSchedule,
+    /// written with every picture, any of which may become a keyframe.
+    keyframe_sei: Vec<SeiMessage>,
+    /// `rc.psz_zones`, which x264 keeps pointing to.
+    zones: Option<CString>,
+}
+
+/// x264 has no thread affinity, the handle may be moved to another thread
+/// as long as it is not used concurrently.
+unsafe impl<T: Send> Send for Encoder<T> {}
+
+impl<T> Encoder<T> {
+    /// create a new encoder, all parameters are copied.
+    ///
+    /// x264's log output is forwarded under the label of `params`, see
+    /// `logging::LogLevel`.
+    pub fn open(params: &Params) -> Result<Encoder<T>> {
+        let mut raw = *params.as_raw();
+        let zones = if params.zones().is_empty() {
+            None
+        } else {
+            let zones = CString::new(zone::format(params.zones())).expect("validated zones");
+            // parsed by x264 into its own copy of the zone list
+            raw.rc.psz_zones = zones.as_ptr() as *mut c_char;
+            raw.rc.zones = ptr::null_mut();
+            raw.rc.i_zones = 0;
+            Some(zones)
+        };
+        let log = LogContext::new(params.log_label().map(ToOwned::to_owned));
+        let mut encoder = unsafe { Encoder::open_with_log(&mut raw, log)? };
+        encoder.zones = zones;
+        encoder.keyframe_sei = params.color_metadata().map(|x| x.sei()).unwrap_or_default();
+        Ok(encoder)
+    }
+
+    /// create a new encoder handler, all parameters from `param` are copied.
+    ///
+    /// `pf_log` and `p_log_private` are replaced so that log output is
+    /// forwarded and errors can be reported through `Error::reason`.
+    ///
+    /// # Safety
+    ///
+    /// Every pointer carried by `param` (filenames, zones, callbacks) must be
+    /// valid for the duration of the call.
+    pub unsafe fn from_raw_params(param: &mut X264ParamT) -> Result<Encoder<T>> {
+        Encoder::open_with_log(param, LogContext::new(None))
+    }
+
+    unsafe fn open_with_log(param: &mut X264ParamT, log: Box<LogContext>) -> Result<Encoder<T>> {
+        log.install(param);
+        let raw = sys::x264_encoder_open(param);
+        if raw.is_null() {
+            return Err(Error::OpenFailed {reason: log.take_last_error()});
+        }
+        let mut submitted: X264ParamT = std::mem::zeroed();
+        sys::x264_encoder_parameters(raw, &mut submitted);
+        Ok(Encoder {
+            raw,
+            width: param.i_width.max(0) as u32,
+            height: param.i_height.max(0) as u32,
+            log,
+            stats: StreamStats::new(param.i_fps_num, param.i_fps_den),
+            quality: QualityStats::new(),
+            reported: Reported::from_param(param),
+            submitted,
+            pending: Vec::new(),
+            keyframes: KeyframeSchedule::default(),
+            keyframe_sei: Vec::new(),
+            zones: None,
+        })
+    }
+
+    /// The underlying encoder handle, still owned by `self`.
+    pub fn as_ptr(&self) -> *mut X264T {
+        self.raw
+    }
+
+    /// return the SPS and PPS that will be used for the whole stream.
+    ///
+    /// the returned NALs borrow the encoder, see `Nals`.
+    pub fn headers(&mut self) -> Result<Nals<'_>> {
+        let mut pp_nal: *mut X264NalT = ptr::null_mut();
+        let mut pi_nal: c_int = 0;
+        self.log.take_last_error();
+        let status = unsafe {
+            sys::x264_encoder_headers(self.raw, &mut pp_nal, &mut pi_nal)
+        };
+        if status < 0 {
+            let reason = self.log.take_last_error();
+            return Err(Error::HeadersFailed {code: status, reason});
+        }
+        Ok(unsafe { nals(pp_nal, pi_nal) })
+    }
+
+    /// encode one picture, tagged with `user`.
+    ///
+    /// returns whichever frame came out, if any, since frames are delayed by
+    /// the lookahead and B-frames. That frame carries the payload given along
+    /// with its own picture.
+    pub fn encode(&mut self, pic: &Picture, user: T) -> Result<Option<EncodedFrame<'_, T>>> {
+        self.encode_with(pic, FrameOptions::default(), user)
+    }
+
+    /// `encode` with per-frame overrides, on top of the keyframe schedule.
+    pub fn encode_with(
+        &mut self,
+        pic: &Picture,
+        options: FrameOptions,
+        user: T,
+    ) -> Result<Option<EncodedFrame<'_, T>>> {
+        if pic.width() < self.width || pic.height() < self.height {
+            return Err(Error::InvalidPicture(format!(
+                "{}x{} picture is smaller than the {}x{} encoder",
+                pic.width(), pic.height(), self.width, self.height,
+            )));
+        }
+        // x264 only reads the input picture, a copy avoids borrowing it mutably
+        let mut pic_in = *pic.as_raw();
+        let mut options = self.keyframes.options_for(pic.pts(), options);
+        options.apply(&mut pic_in);
+        options.sei.splice(0..0, self.keyframe_sei.iter().cloned());
+        let current = self.submitted;
+        options.check(&current)?;
+        if let Some(params) = &options.params {
+            params.check(&current)?;
+        }
+        if let Some(map) = &options.mb_info {
+            map.check(&current)?;
+        }
+        for message in &options.sei {
+            message.check()?;
+        }
+        if let Some(map) = &options.quant_offsets {
+            map.check(&current)?;
+            map.attach(&mut pic_in)?;
+        }
+        if let Err(e) = sei::attach(&options.sei, &mut pic_in) {
+            // x264 would have freed the offsets along with the picture
+            if options.quant_offsets.is_some() {
+                unsafe { quant::free_offsets(pic_in.prop.quant_offsets as *mut c_void) };
+            }
+            return Err(e);
+        }
+        let mut submitted = None;
+        if let Some(params) = &options.params {
+            let mut param = Box::new(current);
+            params.apply(&mut param);
+            submitted = Some(*param);
+            // released by x264 once applied, or along with the frame
+            param.param_free = Some(free_param);
+            pic_in.param = Box::into_raw(param);
+        }
+        let mut slot = Slot {user, mb_info: options.mb_info};
+        if let Some(map) = &mut slot.mb_info {
+            // owned by the slot until the frame comes out, x264 writes the
+            // updated flags back into it
+            pic_in.prop.mb_info = map.as_mut_ptr();
+            pic_in.prop.mb_info_free = None;
+        }
+        unsafe { self.encode_slot(&mut pic_in, slot, submitted) }
+    }
+
+    /// encode with no input picture, draining one of the delayed frames.
+    ///
+    /// should be called until `delayed_frames` returns zero at the end of
+    /// the stream, which `finish` takes care of.
+    pub fn flush(&mut self) -> Result<Option<EncodedFrame<'_, T>>> {
+        unsafe { self.encode_picture(ptr::null_mut(), None) }
+    }
+
+    /// `encode` for a raw input picture.
+    ///
+    /// `opaque` is overwritten with the boxed `user` payload.
+    ///
+    /// # Safety
+    ///
+    /// The image planes of `pic_in` must be valid for its colorspace and the
+    /// configured dimensions.
+    pub unsafe fn encode_raw(
+        &mut self,
+        pic_in: &mut X264PictureT,
+        user: T,
+    ) -> Result<Option<EncodedFrame<'_, T>>> {
+        self.encode_slot(pic_in, Slot {user, mb_info: None}, None)
+    }
+
+    unsafe fn encode_slot(
+        &mut self,
+        pic_in: &mut X264PictureT,
+        slot: Slot<T>,
+        submitted: Option<X264ParamT>,
+    ) -> Result<Option<EncodedFrame<'_, T>>> {
+        let opaque = Box::into_raw(Box::new(slot));
+        // tracked until it comes out, x264 may hold on to the picture even
+        // if this call fails
+        self.pending.push(opaque);
+        pic_in.opaque = opaque as *mut c_void;
+        self.encode_picture(pic_in, submitted)
+    }
+
+    /// `submitted` is the parameters passed along with `pic_in`, if any.
+    unsafe fn encode_picture(
+        &mut self,
+        pic_in: *mut X264PictureT,
+        submitted: Option<X264ParamT>,
+    ) -> Result<Option<EncodedFrame<'_, T>>> {
+        let mut pp_nal: *mut X264NalT = ptr::null_mut();
+        let mut pi_nal: c_int = 0;
+        let mut pic_out: X264PictureT = std::mem::zeroed();
+        self.log.take_last_error();
+        let status = sys::x264_encoder_encode(
+            self.raw,
+            &mut pp_nal,
+            &mut pi_nal,
+            pic_in,
+            &mut pic_out,
+        );
+        if status < 0 {
+            let reason = self.log.take_last_error();
+            return Err(Error::EncodeFailed {code: status, reason});
+        }
+        if let Some(param) = submitted {
+            self.submitted = param;
+        }
+        if status == 0 {
+            return Ok(None);
+        }
+        let slot = self.take_slot(pic_out.opaque as *mut Slot<T>);
+        pic_out.opaque = ptr::null_mut();
+        let frame = EncodedFrame::from_raw(
+            nals(pp_nal, pi_nal),
+            pic_out,
+            slot.user,
+            slot.mb_info,
+            self.reported,
+        );
+        self.stats.record(status as u64, frame.frame_type);
+        self.quality.record(frame.frame_type, &frame.quality());
+        Ok(Some(frame))
+    }
+
+    /// end the stream, returning the frames still held in the lookahead and
+    /// B-frame queues.
+    ///
+    /// the encoder is consumed so that no more pictures can be fed, and is
+    /// closed once the returned iterator is dropped.
+    pub fn finish(self) -> Drain<T> {
+        Drain {encoder: self, failed: false}
+    }
+
+    /// force keyframes at the given pts for pictures passed to `encode`
+    /// and `encode_with`, replacing any previous schedule.
+    pub fn set_keyframe_schedule(&mut self, schedule: KeyframeSchedule) {
+        self.keyframes = schedule;
+    }
+
+    pub fn keyframe_schedule(&self) -> &KeyframeSchedule {
+        &self.keyframes
+    }
+
+    /// totals over every frame output so far.
+    pub fn stats(&self) -> StreamStats {
+        self.stats
+    }
+
+    /// quality metrics over every frame output so far, see `FrameQuality`.
+    pub fn quality_stats(&self) -> QualityStats {
+        self.quality
+    }
+
+    /// apply new parameters, taking effect on whichever frame is encoded next.
+    ///
+    /// not all parameters can be changed; see x264_encoder_reconfig.
+    pub fn reconfig(&mut self, params: &Params) -> Result<()> {
+        let mut raw = *params.as_raw();
+        unsafe { self.reconfig_raw(&mut raw) }
+    }
+
+    /// `reconfig` for a raw parameter struct.
+    ///
+    /// # Safety
+    ///
+    /// Same requirements on `param` as `from_raw_params`.
+    pub unsafe fn reconfig_raw(&mut self, param: &mut X264ParamT) -> Result<()> {
+        self.log.install(param);
+        self.log.take_last_error();
+        let status = sys::x264_encoder_reconfig(self.raw, param);
+        if status < 0 {
+            let reason = self.log.take_last_error();
+            return Err(Error::ReconfigRejected {code: status, reason});
+        }
+        self.submitted = self.parameters();
+        Ok(())
+    }
+
+    /// the current internal set of parameters.
+    ///
+    /// note that the data accessible through pointers in the returned struct
+    /// (e.g. filenames) is owned by the encoder and must not be modified.
+    pub fn parameters(&self) -> X264ParamT {
+        unsafe {
+            let mut param: X264ParamT = std::mem::zeroed();
+            sys::x264_encoder_parameters(self.raw, &mut param);
+            param
+        }
+    }
+
+    /// the number of macroblocks as (columns, rows).
+    pub fn mb_grid(&self) -> (u32, u32) {
+        quant::mb_grid(&self.parameters())
+    }
+
+    /// the number of currently delayed (buffered) frames.
+    pub fn delayed_frames(&self) -> usize {
+        unsafe { sys::x264_encoder_delayed_frames(self.raw).max(0) as usize }
+    }
+
+    /// the maximum number of delayed frames that can occur with the current
+    /// parameters.
+    pub fn maximum_delayed_frames(&self) -> usize {
+        unsafe { sys::x264_encoder_maximum_delayed_frames(self.raw).max(0) as usize }
+    }
+
+    /// begin an intra refresh with the next P-frame, or as soon as the
+    /// current one finishes. Requires that b_intra_refresh be set.
+    pub fn intra_refresh(&mut self) {
+        unsafe { sys::x264_encoder_intra_refresh(self.raw) }
+    }
+
+    /// forget the reference frames from `pts` onwards, e.g. after the client
+    /// reported their loss, forcing a keyframe if none is left.
+    ///
+    /// not supported with B-frames or intra refresh.
+    pub fn invalidate_reference(&mut self, pts: i64) -> Result<()> {
+        self.log.take_last_error();
+        let status = unsafe { sys::x264_encoder_invalidate_reference(self.raw, pts) };
+        if status < 0 {
+            let reason = self.log.take_last_error();
+            return Err(Error::InvalidateFailed {code: status, reason});
+        }
+        Ok(())
+    }
+
+    /// reclaim the payload x264 returned in `opaque`.
+    unsafe fn take_slot(&mut self, opaque: *mut Slot<T>) -> Slot<T> {
+        let index = self.pending
+            .iter()
+            .position(|x| *x == opaque)
+            .expect("pic_out.opaque was not set by this encoder");
+        self.pending.swap_remove(index);
+        *Box::from_raw(opaque)
+    }
+}
+
+/// What is boxed into `opaque` while a picture is in flight.
+struct Slot<T> {
+    user: T,
+    /// x264 may write to the map until the frame comes out.
+    mb_info: Option<MbInfoMap>,
+}
+
+impl<T> Drop for Encoder<T> {
+    fn drop(&mut self) {
+        unsafe {
+            sys::x264_encoder_close(self.raw);
+            for opaque in self.pending.drain(..) {
+                drop(Box::from_raw(opaque));
+            }
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// DRAIN
+///////////////////////////////////////////////////////////////////////////////
+
+/// Iterator over the delayed frames of a finished encoder, see
+/// `Encoder::finish`.
+///
+/// Ends once `x264_encoder_delayed_frames` reaches zero, or after the first
+/// error. The frames are copied out since the encoder is owned by the iterator.
+pub struct Drain<T = ()> {
+    encoder: Encoder<T>,
+    failed: bool,
+}
+
+impl<T> Drain<T> {
+    /// totals over every frame output by the encoder, final once the
+    /// iterator is exhausted.
+    pub fn stats(&self) -> StreamStats {
+        self.encoder.stats
+    }
+
+    /// quality metrics over every frame output by the encoder, see
+    /// `Encoder::quality_stats`.
+    pub fn quality_stats(&self) -> QualityStats {
+        self.encoder.quality
+    }
+}
+
+impl<T> Iterator for Drain<T> {
+    type Item = Result<OwnedEncodedFrame<T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // a delayed frame may come out without any NALs when frame threads
+        // are still busy, keep flushing until something is returned
+        while !self.failed && self.encoder.delayed_frames() > 0 {
+            match self.encoder.flush() {
+                Ok(None) => continue,
+                Ok(Some(frame)) => return Some(Ok(frame.into_owned())),
+                Err(error) => {
+                    self.failed = true;
+                    return Some(Err(error));
+                }
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.failed {
+            (0, Some(0))
+        } else {
+            (0, Some(self.encoder.delayed_frames()))
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// STREAM STATS
+///////////////////////////////////////////////////////////////////////////////
+
+/// Totals over the frames output by an encoder.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct StreamStats {
+    pub frames: u64,
+    /// total size of the NAL payloads.
+    pub bytes: u64,
+    /// IDR and I frames.
+    pub i_frames: u64,
+    pub p_frames: u64,
+    /// B and B-ref frames.
+    pub b_frames: u64,
+    fps_num: u32,
+    fps_den: u32,
+}
+
+impl StreamStats {
+    fn new(fps_num: u32, fps_den: u32) -> Self {
+        StreamStats {fps_num, fps_den, ..StreamStats::default()}
+    }
+
+    fn record(&mut self, bytes: u64, frame_type: FrameType) {
+        self.frames += 1;
+        self.bytes += bytes;
+        if frame_type.is_intra() {
+            self.i_frames += 1;
+        } else if frame_type.is_b() {
+            self.b_frames += 1;
+        } else {
+            self.p_frames += 1;
+        }
+    }
+
+    /// duration of the frames in seconds, at the configured framerate.
+    pub fn duration(&self) -> f64 {
+        if self.fps_num == 0 {
+            return 0.0;
+        }
+        self.frames as f64 * f64::from(self.fps_den) / f64::from(self.fps_num)
+    }
+
+    /// average bitrate in kbit/sec, as in x264's final summary.
+    pub fn bitrate(&self) -> f64 {
+        let duration = self.duration();
+        if duration > 0.0 {
+            self.bytes as f64 * 8.0 / duration / 1000.0
+        } else {
+            0.0
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// INTERNAL HELPERS
+///////////////////////////////////////////////////////////////////////////////
+
+unsafe extern "C" fn free_param(param: *mut c_void) {
+    drop(Box::from_raw(param as *mut X264ParamT));
+}
+
+unsafe fn nals<'a>(pp_nal: *mut X264NalT, pi_nal: c_int) -> Nals<'a> {
+    if pp_nal.is_null() || pi_nal <= 0 {
+        return Nals::empty();
+    }
+    Nals::from_raw(slice::from_raw_parts(pp_nal, pi_nal as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colorspace::Colorspace;
+    use crate::frame::FrameParams;
+
+    #[test]
+    fn successive_frame_params() {
+        let params = Params::builder().size(64, 64).threads(1).build().unwrap();
+        let mut encoder: Encoder = Encoder::open(&params).unwrap();
+        let mut pic = Picture::new(Colorspace::I420, 64, 64).unwrap();
+        for i in 0..8 {
+            pic.set_pts(i);
+            let params = match i {
+                2 => Some(FrameParams {crf: Some(30.0), ..FrameParams::default()}),
+                3 => Some(FrameParams {crf_max: Some(40.0), ..FrameParams::default()}),
+                _ => None,
+            };
+            let options = FrameOptions {params, ..FrameOptions::default()};
+            encoder.encode_with(&pic, options, ()).unwrap();
+        }
+        while encoder.delayed_frames() > 0 {
+            encoder.flush().unwrap();
+        }
+        let param = encoder.parameters();
+        assert_eq!(param.rc.f_rf_constant, 30.0);
+        assert_eq!(param.rc.f_rf_constant_max, 40.0);
+    }
+}
@@ -0,0 +1,183 @@
+use std::fmt;
+use std::io;
+use std::os::raw::c_int;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::level::{Level, LevelViolation};
+
+/// Errors surfaced by the safe wrappers in place of raw negative return codes
+/// and null pointers.
+///
+/// Failures inside the encoder carry the last error message x264 logged, if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// x264_param_parse returned X264_PARAM_BAD_NAME.
+    BadParamName(String),
+    /// x264_param_parse returned X264_PARAM_BAD_VALUE.
+    BadParamValue {
+        name: String,
+        value: Option<String>,
+    },
+    /// the combination of options was rejected before reaching x264.
+    InvalidParams(String),
+    /// x264_param_default_preset rejected the preset or tune name.
+    InvalidPreset(String),
+    /// x264_param_apply_profile rejected the profile.
+    InvalidProfile(String),
+    /// the parameters exceed a limit of the requested `i_level_idc`.
+    LevelExceeded {
+        level: Level,
+        violation: LevelViolation,
+    },
+    /// the picture does not match its colorspace or the encoder.
+    InvalidPicture(String),
+    /// a user SEI payload x264 could not write as is.
+    InvalidSei(String),
+    /// x264_picture_alloc failed, either on malloc or an unsupported colorspace.
+    AllocFailed,
+    /// a file or directory managed by the wrapper could not be accessed.
+    Io {
+        path: PathBuf,
+        source: IoError,
+    },
+    /// a stats or mbtree file of a previous pass is malformed.
+    InvalidStats {
+        /// line of the stats file counting from 1, `None` for a lone record
+        /// or the mbtree file.
+        line: Option<usize>,
+        reason: String,
+    },
+    /// a `FrameSource` did not yield as many pictures as on the first pass.
+    FrameCountMismatch {
+        pass: u32,
+        expected: u64,
+        actual: u64,
+    },
+    /// x264_encoder_open returned a null handle.
+    OpenFailed {
+        reason: Option<String>,
+    },
+    /// x264_encoder_headers returned a negative value.
+    HeadersFailed {
+        code: c_int,
+        reason: Option<String>,
+    },
+    /// x264_encoder_encode returned a negative value.
+    EncodeFailed {
+        code: c_int,
+        reason: Option<String>,
+    },
+    /// x264_encoder_reconfig rejected the new parameters.
+    ReconfigRejected {
+        code: c_int,
+        reason: Option<String>,
+    },
+    /// x264_encoder_invalidate_reference returned a negative value.
+    InvalidateFailed {
+        code: c_int,
+        reason: Option<String>,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub(crate) fn io(path: &Path, source: io::Error) -> Self {
+        Error::Io {path: path.to_owned(), source: IoError(Arc::new(source))}
+    }
+
+    /// The human-readable reason reported by x264, if it logged one.
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            Error::OpenFailed {reason}
+            | Error::HeadersFailed {reason, ..}
+            | Error::EncodeFailed {reason, ..}
+            | Error::ReconfigRejected {reason, ..}
+            | Error::InvalidateFailed {reason, ..} => reason.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::BadParamName(name) => write!(f, "unknown option {:?}", name),
+            Error::BadParamValue {name, value: Some(value)} => {
+                write!(f, "invalid value {:?} for option {:?}", value, name)
+            }
+            Error::BadParamValue {name, value: None} => {
+                write!(f, "option {:?} requires a value", name)
+            }
+            Error::InvalidParams(reason) => write!(f, "invalid parameters: {}", reason),
+            Error::InvalidPreset(name) => write!(f, "invalid preset or tune {:?}", name),
+            Error::InvalidProfile(name) => write!(f, "invalid profile {:?}", name),
+            Error::LevelExceeded {level, violation} => {
+                write!(f, "level {} exceeded: {}", level, violation)
+            }
+            Error::InvalidPicture(reason) => write!(f, "invalid picture: {}", reason),
+            Error::InvalidSei(reason) => write!(f, "invalid SEI: {}", reason),
+            Error::AllocFailed => write!(f, "x264_picture_alloc failed"),
+            Error::Io {path, source} => write!(f, "{}: {}", path.display(), source),
+            Error::InvalidStats {line: Some(line), reason} => {
+                write!(f, "invalid stats at line {}: {}", line, reason)
+            }
+            Error::InvalidStats {line: None, reason} => write!(f, "invalid stats: {}", reason),
+            Error::FrameCountMismatch {pass, expected, actual} => {
+                write!(f, "pass {} read {} pictures instead of {}", pass, actual, expected)
+            }
+            Error::OpenFailed {..} => write!(f, "x264_encoder_open failed"),
+            Error::HeadersFailed {code, ..} => write!(f, "x264_encoder_headers failed ({})", code),
+            Error::EncodeFailed {code, ..} => write!(f, "x264_encoder_encode failed ({})", code),
+            Error::ReconfigRejected {code, ..} => {
+                write!(f, "x264_encoder_reconfig rejected parameters ({})", code)
+            }
+            Error::InvalidateFailed {code, ..} => {
+                write!(f, "x264_encoder_invalidate_reference failed ({})", code)
+            }
+        }?;
+        match self.reason() {
+            Some(reason) => write!(f, ": {}", reason),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io {source, ..} => Some(source.get_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// An `io::Error`, shared so that `Error` can be cloned and compared by kind
+/// and message.
+#[derive(Debug, Clone)]
+pub struct IoError(Arc<io::Error>);
+
+impl IoError {
+    pub fn kind(&self) -> io::ErrorKind {
+        self.0.kind()
+    }
+
+    pub fn get_ref(&self) -> &io::Error {
+        &self.0
+    }
+}
+
+impl PartialEq for IoError {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind() == other.kind() && self.0.to_string() == other.0.to_string()
+    }
+}
+
+impl Eq for IoError {}
+
+impl fmt::Display for IoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
@@ -0,0 +1,564 @@
+use std::collections::BTreeSet;
+use std::os::raw::c_int;
+
+use crate::error::{Error, Result};
+use crate::mb_info::MbInfoMap;
+use crate::nal::{Nals, OwnedNal};
+use crate::params::{
+    BPyramid,
+    CropRect,
+    DirectPrediction,
+    MotionEstimation,
+    Partitions,
+    Trellis,
+};
+use crate::quality::FrameQuality;
+use crate::quant::QuantOffsetMap;
+use crate::raw;
+use crate::sei::SeiMessage;
+use crate::sys::{X264HrdT, X264ParamT, X264PictureT};
+
+///////////////////////////////////////////////////////////////////////////////
+// FRAME TYPE
+///////////////////////////////////////////////////////////////////////////////
+
+/// Slice type of a picture (`X264_TYPE_*`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FrameType {
+    Idr = raw::X264_TYPE_IDR as isize,
+    I = raw::X264_TYPE_I as isize,
+    P = raw::X264_TYPE_P as isize,
+    /// non-disposable B-frame.
+    BRef = raw::X264_TYPE_BREF as isize,
+    B = raw::X264_TYPE_B as isize,
+    /// IDR or I depending on b_open_gop option.
+    Keyframe = raw::X264_TYPE_KEYFRAME as isize,
+}
+
+impl FrameType {
+    pub fn to_raw(self) -> c_int {
+        self as c_int
+    }
+
+    /// `None` for `X264_TYPE_AUTO` or an unknown value.
+    pub fn from_raw(i_type: c_int) -> Option<Self> {
+        match i_type as u32 {
+            raw::X264_TYPE_IDR => Some(FrameType::Idr),
+            raw::X264_TYPE_I => Some(FrameType::I),
+            raw::X264_TYPE_P => Some(FrameType::P),
+            raw::X264_TYPE_BREF => Some(FrameType::BRef),
+            raw::X264_TYPE_B => Some(FrameType::B),
+            raw::X264_TYPE_KEYFRAME => Some(FrameType::Keyframe),
+            _ => None,
+        }
+    }
+
+    /// `IS_X264_TYPE_I`
+    pub fn is_intra(self) -> bool {
+        matches!(self, FrameType::Idr | FrameType::I | FrameType::Keyframe)
+    }
+
+    /// `IS_X264_TYPE_B`
+    pub fn is_b(self) -> bool {
+        matches!(self, FrameType::B | FrameType::BRef)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// FRAME OPTIONS
+///////////////////////////////////////////////////////////////////////////////
+
+/// Per-frame overrides of the encoder's decisions for an input picture.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FrameOptions {
+    /// force the picture type, `None` lets x264 decide.
+    ///
+    /// If x264 encoding parameters are violated in the forcing of picture
+    /// types, x264 will correct the input picture type and log a warning.
+    pub frame_type: Option<FrameType>,
+    /// force the quantizer, `None` leaves it to ratecontrol.
+    ///
+    /// At most QP_MAX, i.e. 69 plus 6 per bit of depth above 8.
+    pub qp: Option<u8>,
+    /// parameters changed from this frame forwards, see `FrameParams`.
+    pub params: Option<FrameParams>,
+    /// region of interest offsets, see `QuantOffsetMap`.
+    pub quant_offsets: Option<QuantOffsetMap>,
+    /// macroblock flags, returned along with the frame, see `MbInfoMap`.
+    pub mb_info: Option<MbInfoMap>,
+    /// user SEI written along with the frame, see `SeiMessage`.
+    pub sei: Vec<SeiMessage>,
+}
+
+impl FrameOptions {
+    pub fn with_frame_type(self, frame_type: FrameType) -> Self {
+        FrameOptions {frame_type: Some(frame_type), ..self}
+    }
+    pub fn with_qp(self, qp: u8) -> Self {
+        FrameOptions {qp: Some(qp), ..self}
+    }
+    pub fn with_params(self, params: FrameParams) -> Self {
+        FrameOptions {params: Some(params), ..self}
+    }
+    pub fn with_quant_offsets(self, map: QuantOffsetMap) -> Self {
+        FrameOptions {quant_offsets: Some(map), ..self}
+    }
+    pub fn with_mb_info(self, map: MbInfoMap) -> Self {
+        FrameOptions {mb_info: Some(map), ..self}
+    }
+    pub fn with_sei(mut self, message: SeiMessage) -> Self {
+        self.sei.push(message);
+        self
+    }
+    /// Rejects a forced quantizer above QP_MAX for the bit depth of the
+    /// `current` parameters.
+    pub(crate) fn check(&self, current: &X264ParamT) -> Result<()> {
+        let qp_max = 69 + 6 * (current.i_bitdepth - 8);
+        match self.qp {
+            Some(qp) if c_int::from(qp) > qp_max => Err(Error::InvalidParams(format!(
+                "qp {} is out of range 0..={}",
+                qp, qp_max,
+            ))),
+            _ => Ok(()),
+        }
+    }
+    pub(crate) fn apply(&self, pic: &mut X264PictureT) {
+        pic.i_type = self.frame_type
+            .map(FrameType::to_raw)
+            .unwrap_or(raw::X264_TYPE_AUTO as c_int);
+        pic.i_qpplus1 = self.qp
+            .map(|qp| c_int::from(qp) + 1)
+            .unwrap_or(raw::X264_QP_AUTO as c_int);
+    }
+}
+
+/// The parameters x264 can change from a given frame onwards, in coded
+/// order, through `X264PictureT::param`.
+///
+/// Unlike x264_encoder_reconfig, which applies on whichever frame is encoded
+/// next, these take effect exactly on the frame they are passed with. Fields
+/// left to `None` keep the value of the previous picture, including changes
+/// passed with pictures not coded yet; anything x264 would not honour per
+/// frame has no field here. Some changes additionally depend on
+/// how the encoder was opened, those are checked when encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FrameParams {
+    // RATE CONTROL
+    /// new CRF, requires CRF ratecontrol.
+    pub crf: Option<f32>,
+    /// maximum CRF as caused by VBV.
+    pub crf_max: Option<f32>,
+    /// VBV (max bitrate, buffer size) in kbit, requires VBV to have been
+    /// enabled when opening.
+    pub vbv: Option<(u32, u32)>,
+    /// ABR bitrate in kbit/sec, requires VBV like `vbv`.
+    pub bitrate: Option<u32>,
+
+    // FRAME TYPES
+    /// never uses more references than initially specified.
+    pub frame_reference: Option<u8>,
+    pub b_bias: Option<i32>,
+    /// requires scenecut detection to have been enabled when opening.
+    pub scenecut_threshold: Option<u32>,
+    /// only honoured with B-pyramid enabled when opening.
+    pub b_pyramid: Option<BPyramid>,
+
+    // BITSTREAM
+    /// loop filter offsets, `Some(None)` disables it.
+    pub deblock: Option<Option<(i8, i8)>>,
+    /// top field first, for interlaced input.
+    pub tff: Option<bool>,
+    pub crop: Option<CropRect>,
+
+    // ANALYSE
+    pub intra_partitions: Option<Partitions>,
+    pub inter_partitions: Option<Partitions>,
+    /// only honoured if the 8x8 transform was enabled when opening.
+    pub transform_8x8: Option<bool>,
+    pub direct_prediction: Option<DirectPrediction>,
+    /// ESA and TESA require one of them to have been used when opening.
+    pub motion_estimation: Option<MotionEstimation>,
+    /// can not be increased with ESA or TESA.
+    pub me_range: Option<u32>,
+    /// requires a subpel refine other than 0 when opening.
+    pub subpel_refine: Option<u8>,
+    pub trellis: Option<Trellis>,
+    pub chroma_me: Option<bool>,
+    pub dct_decimate: Option<bool>,
+    pub fast_pskip: Option<bool>,
+    pub mixed_references: Option<bool>,
+    pub noise_reduction: Option<u32>,
+    /// psy RD and psy trellis strengths.
+    pub psy_rd: Option<(f32, f32)>,
+
+    // SLICES
+    pub slice_max_size: Option<u32>,
+    pub slice_max_mbs: Option<u32>,
+    pub slice_min_mbs: Option<u32>,
+    pub slice_count: Option<u32>,
+    pub slice_count_max: Option<u32>,
+}
+
+impl FrameParams {
+    /// Rejects the changes x264 would silently ignore given the `current`
+    /// parameters of the encoder.
+    pub(crate) fn check(&self, current: &X264ParamT) -> Result<()> {
+        let invalid = |reason: &str| Err(Error::InvalidParams(reason.to_owned()));
+        let vbv = current.rc.i_vbv_max_bitrate > 0 && current.rc.i_vbv_buffer_size > 0;
+        if (self.vbv.is_some() || self.bitrate.is_some()) && !vbv {
+            return invalid("VBV can't be turned on if it wasn't on to begin with");
+        }
+        if let Some((max_bitrate, buffer_size)) = self.vbv {
+            if max_bitrate == 0 || buffer_size == 0 {
+                return invalid("VBV can't be turned off once on");
+            }
+        }
+        if self.crf.is_some() && current.rc.i_rc_method != raw::X264_RC_CRF as c_int {
+            return invalid("crf requires CRF ratecontrol");
+        }
+        if self.scenecut_threshold.is_some() && current.i_scenecut_threshold == 0 {
+            return invalid("scenecut can't be turned on if it wasn't on to begin with");
+        }
+        if self.subpel_refine.is_some() && current.analyse.i_subpel_refine == 0 {
+            return invalid("can't switch out of subme=0 during encoding");
+        }
+        let esa = |x: c_int| x >= raw::X264_ME_ESA as c_int;
+        match self.motion_estimation {
+            Some(me) if esa(me as c_int) && !esa(current.analyse.i_me_method) => {
+                return invalid("can't switch to esa or tesa during encoding");
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub(crate) fn apply(&self, p: &mut X264ParamT) {
+        fn set<T: Copy>(value: Option<T>, f: impl FnOnce(T)) {
+            if let Some(value) = value {
+                f(value)
+            }
+        }
+        set(self.crf, |x| p.rc.f_rf_constant = x);
+        set(self.crf_max, |x| p.rc.f_rf_constant_max = x);
+        set(self.vbv, |(max_bitrate, buffer_size)| {
+            p.rc.i_vbv_max_bitrate = max_bitrate as c_int;
+            p.rc.i_vbv_buffer_size = buffer_size as c_int;
+        });
+        set(self.bitrate, |x| p.rc.i_bitrate = x as c_int);
+        set(self.frame_reference, |x| p.i_frame_reference = x as c_int);
+        set(self.b_bias, |x| p.i_bframe_bias = x as c_int);
+        set(self.scenecut_threshold, |x| p.i_scenecut_threshold = x as c_int);
+        set(self.b_pyramid, |x| p.i_bframe_pyramid = x as c_int);
+        set(self.deblock, |x| match x {
+            Some((alpha, beta)) => {
+                p.b_deblocking_filter = 1;
+                p.i_deblocking_filter_alphac0 = alpha as c_int;
+                p.i_deblocking_filter_beta = beta as c_int;
+            }
+            None => {
+                p.b_deblocking_filter = 0;
+            }
+        });
+        set(self.tff, |x| p.b_tff = x as c_int);
+        set(self.crop, |rect| {
+            p.crop_rect.i_left = rect.left;
+            p.crop_rect.i_top = rect.top;
+            p.crop_rect.i_right = rect.right;
+            p.crop_rect.i_bottom = rect.bottom;
+        });
+        set(self.intra_partitions, |x| p.analyse.intra = x.to_flags());
+        set(self.inter_partitions, |x| p.analyse.inter = x.to_flags());
+        set(self.transform_8x8, |x| p.analyse.b_transform_8x8 = x as c_int);
+        set(self.direct_prediction, |x| p.analyse.i_direct_mv_pred = x as c_int);
+        set(self.motion_estimation, |x| p.analyse.i_me_method = x as c_int);
+        set(self.me_range, |x| p.analyse.i_me_range = x as c_int);
+        set(self.subpel_refine, |x| p.analyse.i_subpel_refine = x as c_int);
+        set(self.trellis, |x| p.analyse.i_trellis = x as c_int);
+        set(self.chroma_me, |x| p.analyse.b_chroma_me = x as c_int);
+        set(self.dct_decimate, |x| p.analyse.b_dct_decimate = x as c_int);
+        set(self.fast_pskip, |x| p.analyse.b_fast_pskip = x as c_int);
+        set(self.mixed_references, |x| p.analyse.b_mixed_references = x as c_int);
+        set(self.noise_reduction, |x| p.analyse.i_noise_reduction = x as c_int);
+        set(self.psy_rd, |(rd, trellis)| {
+            p.analyse.f_psy_rd = rd;
+            p.analyse.f_psy_trellis = trellis;
+        });
+        set(self.slice_max_size, |x| p.i_slice_max_size = x as c_int);
+        set(self.slice_max_mbs, |x| p.i_slice_max_mbs = x as c_int);
+        set(self.slice_min_mbs, |x| p.i_slice_min_mbs = x as c_int);
+        set(self.slice_count, |x| p.i_slice_count = x as c_int);
+        set(self.slice_count_max, |x| p.i_slice_count_max = x as c_int);
+    }
+}
+
+/// Presentation timestamps at which keyframes must land, e.g. cut points.
+///
+/// A keyframe is forced on the first picture whose pts reaches each
+/// scheduled value, so a schedule still holds when the exact pts is never
+/// fed to the encoder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyframeSchedule {
+    pts: BTreeSet<i64>,
+    frame_type: FrameType,
+}
+
+impl Default for KeyframeSchedule {
+    fn default() -> Self {
+        KeyframeSchedule {pts: BTreeSet::new(), frame_type: FrameType::Keyframe}
+    }
+}
+
+impl KeyframeSchedule {
+    /// force `FrameType::Keyframe`, IDR or I depending on b_open_gop, at
+    /// each of the given pts.
+    pub fn new(pts: impl IntoIterator<Item = i64>) -> Self {
+        KeyframeSchedule {pts: pts.into_iter().collect(), ..KeyframeSchedule::default()}
+    }
+    /// the type forced at scheduled pts, such as `FrameType::Idr` for
+    /// closed GOPs regardless of b_open_gop.
+    pub fn with_frame_type(self, frame_type: FrameType) -> Self {
+        KeyframeSchedule {frame_type, ..self}
+    }
+    pub fn insert(&mut self, pts: i64) {
+        self.pts.insert(pts);
+    }
+    /// the scheduled pts not reached yet.
+    pub fn pending(&self) -> impl Iterator<Item = i64> + '_ {
+        self.pts.iter().cloned()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.pts.is_empty()
+    }
+    /// the options for a picture at `pts`, consuming every scheduled value
+    /// it reaches. An explicit frame type in `options` takes precedence, the
+    /// scheduled values are then only consumed if it is an intra type.
+    pub fn options_for(&mut self, pts: i64, options: FrameOptions) -> FrameOptions {
+        let due = self.pts.range(..=pts).next().is_some();
+        let consumed = match options.frame_type {
+            Some(frame_type) => frame_type.is_intra(),
+            None => true,
+        };
+        if consumed {
+            self.pts.retain(|x| *x > pts);
+        }
+        match options.frame_type {
+            None if due => options.with_frame_type(self.frame_type),
+            _ => options,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// HRD TIMING
+///////////////////////////////////////////////////////////////////////////////
+
+/// HRD timing of an output frame, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HrdTiming {
+    pub cpb_initial_arrival_time: f64,
+    pub cpb_final_arrival_time: f64,
+    pub cpb_removal_time: f64,
+    pub dpb_output_time: f64,
+}
+
+impl From<&X264HrdT> for HrdTiming {
+    fn from(hrd: &X264HrdT) -> Self {
+        HrdTiming {
+            cpb_initial_arrival_time: hrd.cpb_initial_arrival_time,
+            cpb_final_arrival_time: hrd.cpb_final_arrival_time,
+            cpb_removal_time: hrd.cpb_removal_time,
+            dpb_output_time: hrd.dpb_output_time,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ENCODED FRAME
+///////////////////////////////////////////////////////////////////////////////
+
+/// Which of the optional outputs x264 fills in `pic_out`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct Reported {
+    pub hrd: bool,
+    pub psnr: bool,
+    pub ssim: bool,
+}
+
+impl Reported {
+    pub fn from_param(param: &X264ParamT) -> Self {
+        Reported {
+            hrd: param.i_nal_hrd != raw::X264_NAL_HRD_NONE as c_int,
+            psnr: param.analyse.b_psnr != 0,
+            ssim: param.analyse.b_ssim != 0,
+        }
+    }
+}
+
+/// A frame output by the encoder, along with the metadata x264 reports for
+/// it in `pic_out` and the user payload of its input picture.
+///
+/// The NALs borrow the encoder, see `Nals`.
+#[derive(Debug, Clone)]
+pub struct EncodedFrame<'enc, T = ()> {
+    pub nals: Nals<'enc>,
+    pub frame_type: FrameType,
+    /// whether this frame is a keyframe, which is not necessarily an IDR
+    /// when SEI recovery points are used instead.
+    pub keyframe: bool,
+    /// pts of the encoded picture, as given on input.
+    pub pts: i64,
+    /// when the pts of the first frame is close to zero, initial frames may
+    /// have a negative dts which must be dealt with by any muxer.
+    pub dts: i64,
+    /// only output when `i_nal_hrd` is set.
+    pub hrd: Option<HrdTiming>,
+    /// the payload passed along with the input picture.
+    pub user: T,
+    /// the map passed along with the input picture, with the constant flags
+    /// updated by x264 if `b_mb_info_update` is set.
+    pub mb_info: Option<MbInfoMap>,
+    quality: FrameQuality,
+    raw: X264PictureT,
+}
+
+impl<'enc, T> EncodedFrame<'enc, T> {
+    pub(crate) fn from_raw(
+        nals: Nals<'enc>,
+        pic_out: X264PictureT,
+        user: T,
+        mb_info: Option<MbInfoMap>,
+        reported: Reported,
+    ) -> Self {
+        EncodedFrame {
+            nals,
+            // x264 always reports the actual type on output
+            frame_type: FrameType::from_raw(pic_out.i_type).unwrap_or(FrameType::P),
+            keyframe: pic_out.b_keyframe != 0,
+            pts: pic_out.i_pts,
+            dts: pic_out.i_dts,
+            hrd: if reported.hrd {Some(HrdTiming::from(&pic_out.hrd_timing))} else {None},
+            user,
+            mb_info,
+            quality: FrameQuality::from_raw(&pic_out.prop, reported.psnr, reported.ssim),
+            raw: pic_out,
+        }
+    }
+
+    /// The output picture as returned by x264_encoder_encode, with `opaque`
+    /// cleared since the payload was moved into `user`.
+    ///
+    /// The reconstructed image it points to is owned by the encoder and only
+    /// valid for as long as the NALs are.
+    pub fn as_raw(&self) -> &X264PictureT {
+        &self.raw
+    }
+
+    /// Total size of the NAL payloads in bytes.
+    pub fn size(&self) -> usize {
+        self.nals.as_bytes().len()
+    }
+
+    pub fn quality(&self) -> FrameQuality {
+        self.quality
+    }
+
+    pub fn into_owned(self) -> OwnedEncodedFrame<T> {
+        OwnedEncodedFrame {
+            nals: self.nals.into_owned(),
+            frame_type: self.frame_type,
+            keyframe: self.keyframe,
+            pts: self.pts,
+            dts: self.dts,
+            hrd: self.hrd,
+            user: self.user,
+            mb_info: self.mb_info,
+            quality: self.quality,
+        }
+    }
+}
+
+/// An `EncodedFrame` with its NALs copied out of the encoder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OwnedEncodedFrame<T = ()> {
+    pub nals: Vec<OwnedNal>,
+    pub frame_type: FrameType,
+    pub keyframe: bool,
+    pub pts: i64,
+    pub dts: i64,
+    pub hrd: Option<HrdTiming>,
+    pub user: T,
+    pub mb_info: Option<MbInfoMap>,
+    quality: FrameQuality,
+}
+
+impl<T> OwnedEncodedFrame<T> {
+    pub fn quality(&self) -> FrameQuality {
+        self.quality
+    }
+
+    /// The NAL payloads concatenated, i.e. the frame as written to a stream.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.nals.iter().flat_map(|x| x.payload.iter().cloned()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::Params;
+
+    #[test]
+    fn schedule_consumed_once() {
+        let mut schedule = KeyframeSchedule::new(vec![10, 20]);
+        assert_eq!(schedule.options_for(0, FrameOptions::default()).frame_type, None);
+        // 10 is never fed, the next picture takes the keyframe
+        let options = schedule.options_for(12, FrameOptions::default());
+        assert_eq!(options.frame_type, Some(FrameType::Keyframe));
+        assert_eq!(schedule.pending().collect::<Vec<_>>(), vec![20]);
+        assert_eq!(schedule.options_for(14, FrameOptions::default()).frame_type, None);
+        // both reached at once are consumed by the same picture
+        schedule.insert(25);
+        let options = schedule.options_for(30, FrameOptions::default());
+        assert_eq!(options.frame_type, Some(FrameType::Keyframe));
+        assert!(schedule.is_empty());
+    }
+
+    #[test]
+    fn schedule_frame_type() {
+        let mut schedule = KeyframeSchedule::new(vec![10]).with_frame_type(FrameType::Idr);
+        assert_eq!(schedule.options_for(10, FrameOptions::default()).frame_type, Some(FrameType::Idr));
+    }
+
+    #[test]
+    fn schedule_forced_non_intra() {
+        let mut schedule = KeyframeSchedule::new(vec![10]);
+        for frame_type in [FrameType::P, FrameType::B].iter().cloned() {
+            let options = FrameOptions::default().with_frame_type(frame_type);
+            assert_eq!(schedule.options_for(10, options).frame_type, Some(frame_type));
+            assert_eq!(schedule.pending().collect::<Vec<_>>(), vec![10]);
+        }
+        let options = schedule.options_for(11, FrameOptions::default());
+        assert_eq!(options.frame_type, Some(FrameType::Keyframe));
+        assert!(schedule.is_empty());
+    }
+
+    #[test]
+    fn schedule_forced_intra() {
+        let mut schedule = KeyframeSchedule::new(vec![10]).with_frame_type(FrameType::Idr);
+        let options = FrameOptions::default().with_frame_type(FrameType::I);
+        assert_eq!(schedule.options_for(10, options).frame_type, Some(FrameType::I));
+        assert!(schedule.is_empty());
+    }
+
+    #[test]
+    fn qp_range() {
+        let params = Params::builder().size(64, 48).build().unwrap();
+        assert_eq!(FrameOptions::default().with_qp(69).check(params.as_raw()), Ok(()));
+        assert!(matches!(
+            FrameOptions::default().with_qp(70).check(params.as_raw()),
+            Err(Error::InvalidParams(_)),
+        ));
+        let params = Params::builder().size(64, 48).bit_depth(10).build().unwrap();
+        assert_eq!(FrameOptions::default().with_qp(81).check(params.as_raw()), Ok(()));
+    }
+}
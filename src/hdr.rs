@@ -0,0 +1,290 @@
+use std::os::raw::c_int;
+
+use crate::error::{Error, Result};
+use crate::sei::SeiMessage;
+use crate::sys::X264ParamT;
+use crate::vui::{ColorPrimaries, MatrixCoefficients, TransferCharacteristics};
+
+///////////////////////////////////////////////////////////////////////////////
+// COLOR METADATA
+///////////////////////////////////////////////////////////////////////////////
+
+/// The colour description of a stream: the VUI colour fields, the
+/// alternative transfer SEI, and the colour volume SEI of HDR10.
+///
+/// Set through `ParamsBuilder::color_metadata`. x264 only decides which
+/// frames are keyframes after their picture was passed, e.g. on a scenecut
+/// or at the keyint, so the SEI messages go with every picture. They persist
+/// until the next IDR and must have the same content throughout, repeating
+/// them is harmless.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorMetadata {
+    pub primaries: ColorPrimaries,
+    pub transfer: TransferCharacteristics,
+    pub matrix: MatrixCoefficients,
+    pub full_range: bool,
+    /// chroma sample location, both top & bottom, in `0..=5`; `None` leaves
+    /// it unspecified.
+    pub chroma_loc: Option<u8>,
+    /// preferred transfer characteristics for decoders that support them,
+    /// e.g. HLG over a BT.2020 SDR compatible `transfer`.
+    pub alternative_transfer: Option<TransferCharacteristics>,
+    pub mastering_display: Option<MasteringDisplay>,
+    pub content_light_level: Option<ContentLightLevel>,
+}
+
+impl ColorMetadata {
+    /// SDR HD, limited range.
+    pub fn bt709() -> Self {
+        ColorMetadata {
+            primaries: ColorPrimaries::Bt709,
+            transfer: TransferCharacteristics::Bt709,
+            matrix: MatrixCoefficients::Bt709,
+            full_range: false,
+            chroma_loc: None,
+            alternative_transfer: None,
+            mastering_display: None,
+            content_light_level: None,
+        }
+    }
+
+    /// BT.2020 primaries with the PQ transfer of SMPTE ST 2084, limited
+    /// range and top-left chroma siting as in BT.2100.
+    pub fn hdr10(mastering_display: MasteringDisplay, content_light_level: ContentLightLevel) -> Self {
+        ColorMetadata {
+            primaries: ColorPrimaries::Bt2020,
+            transfer: TransferCharacteristics::Smpte2084,
+            matrix: MatrixCoefficients::Bt2020NC,
+            full_range: false,
+            chroma_loc: Some(2),
+            alternative_transfer: None,
+            mastering_display: Some(mastering_display),
+            content_light_level: Some(content_light_level),
+        }
+    }
+
+    /// BT.2020 primaries with the HLG transfer of ARIB STD-B67.
+    pub fn hlg() -> Self {
+        ColorMetadata {
+            primaries: ColorPrimaries::Bt2020,
+            transfer: TransferCharacteristics::AribStdB67,
+            matrix: MatrixCoefficients::Bt2020NC,
+            full_range: false,
+            chroma_loc: Some(2),
+            alternative_transfer: None,
+            mastering_display: None,
+            content_light_level: None,
+        }
+    }
+
+    /// `hlg`, signalled as BT.2020 for decoders that are not HLG aware.
+    pub fn hlg_compatible() -> Self {
+        ColorMetadata {
+            transfer: TransferCharacteristics::Bt2020_10,
+            alternative_transfer: Some(TransferCharacteristics::AribStdB67),
+            ..ColorMetadata::hlg()
+        }
+    }
+
+    pub fn with_full_range(self, full_range: bool) -> Self {
+        ColorMetadata {full_range, ..self}
+    }
+
+    pub fn with_chroma_loc(self, chroma_loc: u8) -> Self {
+        ColorMetadata {chroma_loc: Some(chroma_loc), ..self}
+    }
+
+    /// The SEI messages written along with every picture.
+    pub fn sei(&self) -> Vec<SeiMessage> {
+        let mastering = self.mastering_display.map(|x| x.to_sei());
+        let light_level = self.content_light_level.map(|x| x.to_sei());
+        mastering.into_iter().chain(light_level).collect()
+    }
+
+    pub(crate) fn check(&self) -> Result<()> {
+        if self.chroma_loc.is_some_and(|x| x > 5) {
+            return Err(Error::InvalidParams(String::from("chroma_loc must be in 0..=5")));
+        }
+        if let Some(mastering) = self.mastering_display {
+            if mastering.min_luminance >= mastering.max_luminance {
+                return Err(Error::InvalidParams(String::from(
+                    "mastering display minimum luminance must be below the maximum",
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn apply(&self, param: &mut X264ParamT) {
+        param.vui.i_colorprim = self.primaries as c_int;
+        param.vui.i_transfer = self.transfer as c_int;
+        param.vui.i_colmatrix = self.matrix as c_int;
+        param.vui.b_fullrange = self.full_range as c_int;
+        if let Some(chroma_loc) = self.chroma_loc {
+            param.vui.i_chroma_loc = chroma_loc as c_int;
+        }
+        param.i_alternative_transfer = self.alternative_transfer
+            .unwrap_or(TransferCharacteristics::Undefined) as c_int;
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// MASTERING DISPLAY
+///////////////////////////////////////////////////////////////////////////////
+
+/// The colour volume of the display the content was mastered on, see
+/// SMPTE ST 2086 (mastering_display_colour_volume SEI).
+///
+/// Chromaticities are in increments of 0.00002 and luminances in increments
+/// of 0.0001 cd/m², as coded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MasteringDisplay {
+    /// (x, y) of the green, blue and red primaries, in that order.
+    pub primaries: [(u16, u16); 3],
+    pub white_point: (u16, u16),
+    pub max_luminance: u32,
+    pub min_luminance: u32,
+}
+
+impl MasteringDisplay {
+    /// mastering_display_colour_volume payload type.
+    pub const PAYLOAD_TYPE: u32 = 137;
+
+    /// From CIE 1931 (x, y) chromaticities and luminances in cd/m².
+    pub fn from_chromaticities(
+        red: (f64, f64),
+        green: (f64, f64),
+        blue: (f64, f64),
+        white_point: (f64, f64),
+        max_luminance: f64,
+        min_luminance: f64,
+    ) -> Self {
+        let xy = |(x, y): (f64, f64)| {
+            ((x / 0.00002).round() as u16, (y / 0.00002).round() as u16)
+        };
+        MasteringDisplay {
+            primaries: [xy(green), xy(blue), xy(red)],
+            white_point: xy(white_point),
+            max_luminance: (max_luminance / 0.0001).round() as u32,
+            min_luminance: (min_luminance / 0.0001).round() as u32,
+        }
+    }
+
+    /// A DCI-P3 display with a D65 white point, as most HDR10 content is
+    /// mastered on.
+    pub fn display_p3(max_luminance: f64, min_luminance: f64) -> Self {
+        MasteringDisplay::from_chromaticities(
+            (0.680, 0.320),
+            (0.265, 0.690),
+            (0.150, 0.060),
+            (0.3127, 0.3290),
+            max_luminance,
+            min_luminance,
+        )
+    }
+
+    /// A display covering BT.2020 with a D65 white point.
+    pub fn bt2020(max_luminance: f64, min_luminance: f64) -> Self {
+        MasteringDisplay::from_chromaticities(
+            (0.708, 0.292),
+            (0.170, 0.797),
+            (0.131, 0.046),
+            (0.3127, 0.3290),
+            max_luminance,
+            min_luminance,
+        )
+    }
+
+    pub fn to_sei(&self) -> SeiMessage {
+        let mut data = Vec::with_capacity(24);
+        for (x, y) in self.primaries {
+            data.extend_from_slice(&x.to_be_bytes());
+            data.extend_from_slice(&y.to_be_bytes());
+        }
+        data.extend_from_slice(&self.white_point.0.to_be_bytes());
+        data.extend_from_slice(&self.white_point.1.to_be_bytes());
+        data.extend_from_slice(&self.max_luminance.to_be_bytes());
+        data.extend_from_slice(&self.min_luminance.to_be_bytes());
+        SeiMessage {payload_type: MasteringDisplay::PAYLOAD_TYPE, data}
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// CONTENT LIGHT LEVEL
+///////////////////////////////////////////////////////////////////////////////
+
+/// Upper bounds of the content's light level in cd/m², as in CTA-861.3
+/// (content_light_level_info SEI).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ContentLightLevel {
+    /// MaxCLL, the brightest pixel of the whole stream.
+    pub max_content: u16,
+    /// MaxFALL, the brightest frame average of the whole stream.
+    pub max_frame_average: u16,
+}
+
+impl ContentLightLevel {
+    /// content_light_level_info payload type.
+    pub const PAYLOAD_TYPE: u32 = 144;
+
+    pub fn new(max_content: u16, max_frame_average: u16) -> Self {
+        ContentLightLevel {max_content, max_frame_average}
+    }
+
+    pub fn to_sei(&self) -> SeiMessage {
+        let mut data = Vec::with_capacity(4);
+        data.extend_from_slice(&self.max_content.to_be_bytes());
+        data.extend_from_slice(&self.max_frame_average.to_be_bytes());
+        SeiMessage {payload_type: ContentLightLevel::PAYLOAD_TYPE, data}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::Params;
+
+    #[test]
+    fn mastering_display_payload() {
+        // G(13250,34500)B(7500,3000)R(34000,16000)WP(15635,16450)L(10000000,1)
+        let sei = MasteringDisplay::display_p3(1000.0, 0.0001).to_sei();
+        assert_eq!(sei.payload_type, 137);
+        assert_eq!(sei.data, [
+            0x33, 0xc2, 0x86, 0xc4,
+            0x1d, 0x4c, 0x0b, 0xb8,
+            0x84, 0xd0, 0x3e, 0x80,
+            0x3d, 0x13, 0x40, 0x42,
+            0x00, 0x98, 0x96, 0x80,
+            0x00, 0x00, 0x00, 0x01,
+        ]);
+    }
+
+    #[test]
+    fn content_light_level_payload() {
+        let sei = ContentLightLevel::new(1000, 400).to_sei();
+        assert_eq!(sei.payload_type, 144);
+        assert_eq!(sei.data, [0x03, 0xe8, 0x01, 0x90]);
+    }
+
+    #[test]
+    fn hdr10_sei() {
+        let color = ColorMetadata::hdr10(
+            MasteringDisplay::display_p3(1000.0, 0.0001),
+            ContentLightLevel::new(1000, 400),
+        );
+        let types = color.sei().iter().map(|x| x.payload_type).collect::<Vec<_>>();
+        assert_eq!(types, [137, 144]);
+        assert!(ColorMetadata::bt709().sei().is_empty());
+    }
+
+    #[test]
+    fn chroma_loc() {
+        assert_eq!(ColorMetadata::bt709().with_chroma_loc(5).check(), Ok(()));
+        assert!(ColorMetadata::bt709().with_chroma_loc(6).check().is_err());
+        let params = Params::builder()
+            .size(64, 48)
+            .color_metadata(ColorMetadata::bt709().with_chroma_loc(6))
+            .build();
+        assert!(matches!(params, Err(Error::InvalidParams(_))));
+    }
+}
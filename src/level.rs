@@ -0,0 +1,469 @@
+use std::fmt;
+use std::os::raw::c_int;
+
+use crate::params::Profile;
+use crate::raw;
+use crate::sys::{X264LevelT, X264ParamT};
+
+///////////////////////////////////////////////////////////////////////////////
+// LEVEL
+///////////////////////////////////////////////////////////////////////////////
+
+/// H.264 levels, in the order of `x264_levels`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    L1,
+    /// signalled as level 1.1 with constraint_set3 in Baseline and Main.
+    L1b,
+    L1_1,
+    L1_2,
+    L1_3,
+    L2,
+    L2_1,
+    L2_2,
+    L3,
+    L3_1,
+    L3_2,
+    L4,
+    L4_1,
+    L4_2,
+    L5,
+    L5_1,
+    L5_2,
+    L6,
+    L6_1,
+    L6_2,
+}
+
+impl Level {
+    pub const ALL: &'static [Level] = &[
+        Level::L1,
+        Level::L1b,
+        Level::L1_1,
+        Level::L1_2,
+        Level::L1_3,
+        Level::L2,
+        Level::L2_1,
+        Level::L2_2,
+        Level::L3,
+        Level::L3_1,
+        Level::L3_2,
+        Level::L4,
+        Level::L4_1,
+        Level::L4_2,
+        Level::L5,
+        Level::L5_1,
+        Level::L5_2,
+        Level::L6,
+        Level::L6_1,
+        Level::L6_2,
+    ];
+
+    /// The `i_level_idc` value, 10 times the level number, and 9 for level 1b.
+    pub fn level_idc(self) -> u8 {
+        match self {
+            Level::L1 => 10,
+            Level::L1b => 9,
+            Level::L1_1 => 11,
+            Level::L1_2 => 12,
+            Level::L1_3 => 13,
+            Level::L2 => 20,
+            Level::L2_1 => 21,
+            Level::L2_2 => 22,
+            Level::L3 => 30,
+            Level::L3_1 => 31,
+            Level::L3_2 => 32,
+            Level::L4 => 40,
+            Level::L4_1 => 41,
+            Level::L4_2 => 42,
+            Level::L5 => 50,
+            Level::L5_1 => 51,
+            Level::L5_2 => 52,
+            Level::L6 => 60,
+            Level::L6_1 => 61,
+            Level::L6_2 => 62,
+        }
+    }
+
+    pub fn from_level_idc(level_idc: u8) -> Option<Self> {
+        Level::ALL.iter().find(|x| x.level_idc() == level_idc).cloned()
+    }
+
+    /// The limits x264 enforces for this level.
+    pub fn limits(self) -> LevelLimits {
+        levels()
+            .iter()
+            .find(|x| x.level_idc == self.level_idc())
+            .map(LevelLimits::from)
+            .expect("level missing from x264_levels")
+    }
+
+    /// The lowest level allowing `width`x`height` progressive frames at `fps`
+    /// with `refs` frames in the DPB and a VBV max bitrate of `bitrate`
+    /// kbit/sec, or `None` if even the highest level is exceeded.
+    ///
+    /// The bitrate is checked against the Baseline & Main limits, which the
+    /// High profiles scale up.
+    pub fn minimum_for(width: u32, height: u32, fps: f64, bitrate: u32, refs: u32) -> Option<Level> {
+        let mb_width = width.div_ceil(16);
+        let mb_height = height.div_ceil(16);
+        let demand = Demand {
+            mb_width,
+            mb_height,
+            dpb_frames: refs,
+            mb_rate: (f64::from(mb_width * mb_height) * fps).ceil() as u64,
+            vbv_max_bitrate: bitrate,
+            vbv_buffer_size: 0,
+            mv_range: None,
+            interlaced: false,
+            cbp_factor: 4,
+        };
+        Level::ALL
+            .iter()
+            .cloned()
+            .find(|level| level.check(&demand).is_ok())
+    }
+
+    /// Checks `param` against this level the way x264_validate_levels does
+    /// once the encoder is opened, reporting the first limit exceeded.
+    ///
+    /// `profile` is the one that will be applied, if any, otherwise it is
+    /// inferred from the parameters like x264 does.
+    pub fn validate(self, param: &X264ParamT, profile: Option<Profile>) -> Result<(), LevelViolation> {
+        self.check(&Demand::from_param(param, profile))
+    }
+
+    fn check(self, demand: &Demand) -> Result<(), LevelViolation> {
+        let l = self.limits();
+        let mbs = demand.mb_width * demand.mb_height;
+        if l.frame_size < mbs
+            || l.frame_size * 8 < demand.mb_width * demand.mb_width
+            || l.frame_size * 8 < demand.mb_height * demand.mb_height
+        {
+            return Err(LevelViolation::FrameSize {
+                mb_width: demand.mb_width,
+                mb_height: demand.mb_height,
+                limit: l.frame_size,
+            });
+        }
+        if mbs * demand.dpb_frames > l.dpb {
+            return Err(LevelViolation::DpbSize {
+                frames: demand.dpb_frames,
+                mbs: mbs * demand.dpb_frames,
+                limit: l.dpb,
+            });
+        }
+        let max_bitrate = l.bitrate * demand.cbp_factor / 4;
+        if demand.vbv_max_bitrate > max_bitrate {
+            return Err(LevelViolation::VbvBitrate {
+                bitrate: demand.vbv_max_bitrate,
+                limit: max_bitrate,
+            });
+        }
+        let max_buffer = l.cpb * demand.cbp_factor / 4;
+        if demand.vbv_buffer_size > max_buffer {
+            return Err(LevelViolation::VbvBuffer {
+                size: demand.vbv_buffer_size,
+                limit: max_buffer,
+            });
+        }
+        match demand.mv_range {
+            Some(range) if range > u32::from(l.mv_range) => {
+                return Err(LevelViolation::MvRange {range, limit: l.mv_range});
+            }
+            _ => {}
+        }
+        if demand.interlaced && l.frame_only {
+            return Err(LevelViolation::Interlaced);
+        }
+        if demand.mb_rate > u64::from(l.mbps) {
+            return Err(LevelViolation::MbRate {
+                rate: demand.mb_rate,
+                limit: l.mbps,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Level::L1b => write!(f, "1b"),
+            _ => write!(f, "{}.{}", self.level_idc() / 10, self.level_idc() % 10),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// LIMITS
+///////////////////////////////////////////////////////////////////////////////
+
+/// Typed copy of an `x264_levels` entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LevelLimits {
+    /// max macroblock processing rate (macroblocks/sec)
+    pub mbps: u32,
+    /// max frame size (macroblocks)
+    pub frame_size: u32,
+    /// max decoded picture buffer (mbs)
+    pub dpb: u32,
+    /// max bitrate (kbit/sec), for Baseline & Main
+    pub bitrate: u32,
+    /// max vbv buffer (kbit), for Baseline & Main
+    pub cpb: u32,
+    /// max vertical mv component range (pixels)
+    pub mv_range: u16,
+    /// max mvs per 2 consecutive mbs.
+    pub mvs_per_2mb: u8,
+    /// slice rate limit
+    pub slice_rate: u8,
+    /// min compression ratio
+    pub mincr: u8,
+    /// limit bipred to >=8x8
+    pub bipred8x8: bool,
+    /// direct_8x8_inference_flag
+    pub direct8x8: bool,
+    /// forbid interlacing
+    pub frame_only: bool,
+}
+
+impl From<&X264LevelT> for LevelLimits {
+    fn from(l: &X264LevelT) -> Self {
+        LevelLimits {
+            mbps: l.mbps,
+            frame_size: l.frame_size,
+            dpb: l.dpb,
+            bitrate: l.bitrate,
+            cpb: l.cpb,
+            mv_range: l.mv_range,
+            mvs_per_2mb: l.mvs_per_2mb,
+            slice_rate: l.slice_rate,
+            mincr: l.mincr,
+            bipred8x8: l.bipred8x8 != 0,
+            direct8x8: l.direct8x8 != 0,
+            frame_only: l.frame_only != 0,
+        }
+    }
+}
+
+/// The entries of `x264_levels`, up to its zeroed terminator.
+fn levels() -> &'static [X264LevelT] {
+    unsafe {
+        let first = std::ptr::addr_of!(raw::x264_levels) as *const X264LevelT;
+        let mut len = 0;
+        while (*first.add(len)).level_idc != 0 {
+            len += 1;
+        }
+        std::slice::from_raw_parts(first, len)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// VIOLATIONS
+///////////////////////////////////////////////////////////////////////////////
+
+/// The level limit a configuration exceeds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LevelViolation {
+    /// frame size in macroblocks.
+    FrameSize {
+        mb_width: u32,
+        mb_height: u32,
+        limit: u32,
+    },
+    /// decoded picture buffer size, in frames and in macroblocks.
+    DpbSize {
+        frames: u32,
+        mbs: u32,
+        limit: u32,
+    },
+    /// macroblocks per second.
+    MbRate {
+        rate: u64,
+        limit: u32,
+    },
+    /// VBV max bitrate in kbit/sec, scaled for the profile.
+    VbvBitrate {
+        bitrate: u32,
+        limit: u32,
+    },
+    /// VBV buffer size in kbit, scaled for the profile.
+    VbvBuffer {
+        size: u32,
+        limit: u32,
+    },
+    /// vertical motion vector range in pixels.
+    MvRange {
+        range: u32,
+        limit: u16,
+    },
+    /// the level only allows progressive frames.
+    Interlaced,
+}
+
+impl fmt::Display for LevelViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LevelViolation::FrameSize {mb_width, mb_height, limit} => write!(
+                f, "frame MB size ({}x{}) > level limit ({})", mb_width, mb_height, limit,
+            ),
+            LevelViolation::DpbSize {frames, mbs, limit} => write!(
+                f, "DPB size ({} frames, {} mbs) > level limit ({} mbs)", frames, mbs, limit,
+            ),
+            LevelViolation::MbRate {rate, limit} => {
+                write!(f, "MB rate ({}) > level limit ({})", rate, limit)
+            }
+            LevelViolation::VbvBitrate {bitrate, limit} => {
+                write!(f, "VBV bitrate ({}) > level limit ({})", bitrate, limit)
+            }
+            LevelViolation::VbvBuffer {size, limit} => {
+                write!(f, "VBV buffer ({}) > level limit ({})", size, limit)
+            }
+            LevelViolation::MvRange {range, limit} => {
+                write!(f, "MV range ({}) > level limit ({})", range, limit)
+            }
+            LevelViolation::Interlaced => write!(f, "interlaced > level limit (progressive only)"),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// INTERNAL
+///////////////////////////////////////////////////////////////////////////////
+
+/// `X264_REF_MAX`, internal to x264.
+const REF_MAX: c_int = 16;
+
+/// What a configuration requires of a level, as computed by x264_sps_init.
+struct Demand {
+    mb_width: u32,
+    mb_height: u32,
+    dpb_frames: u32,
+    mb_rate: u64,
+    vbv_max_bitrate: u32,
+    vbv_buffer_size: u32,
+    /// `None` when left to x264, which then picks one within the level.
+    mv_range: Option<u32>,
+    interlaced: bool,
+    /// bitrate and cpb scaling of the High profiles, in quarters.
+    cbp_factor: u32,
+}
+
+impl Demand {
+    fn from_param(p: &X264ParamT, profile: Option<Profile>) -> Self {
+        let interlaced = p.b_interlaced != 0 || p.b_fake_interlaced != 0;
+        let mb_width = (p.i_width.max(0) as u32).div_ceil(16);
+        let mut mb_height = (p.i_height.max(0) as u32).div_ceil(16);
+        if interlaced {
+            mb_height = (mb_height + 1) & !1;
+        }
+        // x264 drops the pyramid with fewer than 2 B-frames
+        let pyramid = if p.i_bframe > 1 {p.i_bframe_pyramid} else {0};
+        let reorder = if pyramid != 0 {2} else if p.i_bframe > 0 {1} else {0};
+        let dpb_frames = if p.i_keyint_max == 1 {
+            0
+        } else {
+            p.i_frame_reference
+                .max(1 + reorder)
+                .max(if pyramid != 0 {4} else {1})
+                .max(p.i_dpb_size)
+                .min(REF_MAX) as u32
+        };
+        let mbs = u64::from(mb_width * mb_height);
+        let mb_rate = if p.i_fps_den > 0 {
+            mbs * u64::from(p.i_fps_num) / u64::from(p.i_fps_den)
+        } else {
+            0
+        };
+        let cbp_factor = match profile.unwrap_or_else(|| implied_profile(p)) {
+            Profile::High422 | Profile::High444 => 16,
+            Profile::High10 => 12,
+            Profile::High => 5,
+            Profile::Main | Profile::Baseline => 4,
+        };
+        Demand {
+            mb_width,
+            mb_height,
+            dpb_frames,
+            mb_rate,
+            vbv_max_bitrate: p.rc.i_vbv_max_bitrate.max(0) as u32,
+            vbv_buffer_size: p.rc.i_vbv_buffer_size.max(0) as u32,
+            mv_range: if p.analyse.i_mv_range > 0 {Some(p.analyse.i_mv_range as u32)} else {None},
+            interlaced,
+            cbp_factor,
+        }
+    }
+}
+
+/// The profile x264_sps_init picks when none is applied.
+fn implied_profile(p: &X264ParamT) -> Profile {
+    let chroma = p.i_csp as u32 & raw::X264_CSP_MASK;
+    let lossless = p.rc.i_rc_method == raw::X264_RC_CQP as c_int && p.rc.i_qp_constant == 0;
+    if lossless || chroma >= raw::X264_CSP_I444 {
+        Profile::High444
+    } else if chroma >= raw::X264_CSP_I422 {
+        Profile::High422
+    } else if p.i_bitdepth > 8 {
+        Profile::High10
+    } else if p.analyse.b_transform_8x8 != 0
+        || p.i_cqm_preset != raw::X264_CQM_FLAT as c_int
+        || chroma == raw::X264_CSP_I400
+    {
+        Profile::High
+    } else if p.b_cabac != 0
+        || p.i_bframe > 0
+        || p.b_interlaced != 0
+        || p.b_fake_interlaced != 0
+        || p.analyse.i_weighted_pred > 0
+    {
+        Profile::Main
+    } else {
+        Profile::Baseline
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::{Params, RateControl};
+
+    #[test]
+    fn minimum_level() {
+        assert_eq!(Level::minimum_for(1920, 1080, 30.0, 20000, 4), Some(Level::L4));
+        assert_eq!(Level::minimum_for(3840, 2160, 60.0, 0, 4), Some(Level::L5_2));
+        assert_eq!(Level::minimum_for(7680, 4320, 120.0, 0, 1), Some(Level::L6_2));
+        assert_eq!(Level::minimum_for(16384, 16384, 30.0, 0, 1), None);
+    }
+
+    #[test]
+    fn vbv_violation() {
+        let params = Params::builder()
+            .size(1920, 1080)
+            .fps(30, 1)
+            .rate_control(RateControl::Crf(23.0))
+            .vbv(30000, 30000)
+            .build()
+            .unwrap();
+        assert_eq!(Level::L4_1.validate(params.as_raw(), None), Ok(()));
+        // High profile, so 5/4 of the 20000 kbit/sec of Main
+        assert_eq!(
+            Level::L4.validate(params.as_raw(), None),
+            Err(LevelViolation::VbvBitrate {bitrate: 30000, limit: 25000}),
+        );
+    }
+
+    #[test]
+    fn dpb_violation() {
+        let params = Params::builder()
+            .size(1920, 1080)
+            .fps(30, 1)
+            .frame_reference(5)
+            .build()
+            .unwrap();
+        assert_eq!(
+            Level::L4.validate(params.as_raw(), None),
+            Err(LevelViolation::DpbSize {frames: 5, mbs: 5 * 8160, limit: 32768}),
+        );
+    }
+}
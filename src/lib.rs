@@ -4,3 +4,89 @@ pub mod raw;
 /// External x264 FFI
 pub mod sys;
 
+/// Input colorspaces
+pub mod colorspace;
+
+/// Error type shared by the safe API
+pub mod error;
+
+/// Safe, owning encoder handle
+pub mod encoder;
+
+/// Encoded frames and their metadata
+pub mod frame;
+
+/// Colour description of HDR streams
+pub mod hdr;
+
+/// H.264 levels and their limits
+pub mod level;
+
+/// Bridge for x264's `pf_log` callback
+pub mod logging;
+
+/// Per-macroblock info flags
+pub mod mb_info;
+
+/// NAL units returned by the encoder
+pub mod nal;
+
+/// Name tables of the enum-like options
+pub mod names;
+
+/// Typed encoder parameters
+pub mod params;
+
+/// Input pictures
+pub mod picture;
+
+/// Quality metrics of encoded frames
+pub mod quality;
+
+/// Region of interest quantizer offsets
+pub mod quant;
+
+/// Low-latency encoding for interactive streaming
+pub mod realtime;
+
+/// User SEI payloads
+pub mod sei;
+
+/// First-pass stats files
+pub mod stats;
+
+/// Multi-pass encoding
+pub mod twopass;
+
+/// Video usability information
+pub mod vui;
+
+/// Ratecontrol zones
+pub mod zone;
+
+pub use colorspace::{Colorspace, Csp};
+pub use error::{Error, IoError, Result};
+pub use encoder::{Drain, Encoder, StreamStats};
+pub use frame::{
+    EncodedFrame,
+    FrameOptions,
+    FrameParams,
+    FrameType,
+    HrdTiming,
+    KeyframeSchedule,
+    OwnedEncodedFrame,
+};
+pub use hdr::{ColorMetadata, ContentLightLevel, MasteringDisplay};
+pub use level::{Level, LevelLimits, LevelViolation};
+pub use logging::LogLevel;
+pub use mb_info::MbInfoMap;
+pub use nal::{Nal, Nals, NalPriority, NalUnitType, OwnedNal};
+pub use params::{Params, ParamsBuilder, Preset, Profile, PsyTune, RateControl, Tune};
+pub use picture::Picture;
+pub use quality::{FrameQuality, Psnr, QualitySummary, QualityStats};
+pub use quant::{QuantOffsetMap, Region};
+pub use realtime::{LossRecovery, RealtimeEncoder};
+pub use sei::{CcPacket, CcType, SeiMessage};
+pub use stats::{FrameStats, MbTreeFrame, Scene, StatsFile, StatsHeader, WeightStats};
+pub use twopass::{FrameSource, TwoPassEncoder};
+pub use zone::{Zone, ZoneKind};
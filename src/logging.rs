@@ -0,0 +1,140 @@
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_void};
+use std::sync::Mutex;
+
+use crate::raw;
+use crate::sys::{VaListArg, X264ParamT};
+
+extern "C" {
+    fn vsnprintf(s: *mut c_char, n: usize, format: *const c_char, ap: VaListArg) -> c_int;
+}
+
+/// Target used for every record forwarded from x264.
+pub const LOG_TARGET: &str = "x264";
+
+///////////////////////////////////////////////////////////////////////////////
+// LOG LEVEL
+///////////////////////////////////////////////////////////////////////////////
+
+/// Maximum level x264 will log at (`i_log_level`).
+///
+/// Records are forwarded to the `log` crate, or to `tracing` when the
+/// `tracing` feature is enabled, under the `x264` target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LogLevel {
+    None = raw::X264_LOG_NONE as isize,
+    Error = raw::X264_LOG_ERROR as isize,
+    Warning = raw::X264_LOG_WARNING as isize,
+    Info = raw::X264_LOG_INFO as isize,
+    Debug = raw::X264_LOG_DEBUG as isize,
+}
+
+impl LogLevel {
+    pub fn from_raw(level: c_int) -> Self {
+        match level {
+            x if x < 0 => LogLevel::None,
+            0 => LogLevel::Error,
+            1 => LogLevel::Warning,
+            2 => LogLevel::Info,
+            _ => LogLevel::Debug,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// LOG CONTEXT
+///////////////////////////////////////////////////////////////////////////////
+
+/// Per-encoder logging state, handed to x264 through `p_log_private`.
+///
+/// Must stay at a stable address (i.e. boxed) for as long as x264 may log,
+/// which is until x264_encoder_close returns.
+pub(crate) struct LogContext {
+    /// label identifying the encoder in forwarded records.
+    label: Option<String>,
+    last_error: Mutex<Option<String>>,
+}
+
+impl LogContext {
+    pub(crate) fn new(label: Option<String>) -> Box<LogContext> {
+        Box::new(LogContext {
+            label,
+            last_error: Mutex::new(None),
+        })
+    }
+
+    /// Route the log output of `param` through this context.
+    pub(crate) fn install(&self, param: &mut X264ParamT) {
+        param.pf_log = Some(log_callback);
+        param.p_log_private = self as *const LogContext as *mut c_void;
+    }
+
+    /// The last error message logged since this was last called.
+    pub(crate) fn take_last_error(&self) -> Option<String> {
+        self.last_error
+            .lock()
+            .ok()
+            .and_then(|mut x| x.take())
+    }
+
+    fn log(&self, level: LogLevel, message: &str) {
+        let message = message.trim_end();
+        if level == LogLevel::Error {
+            if let Ok(mut last_error) = self.last_error.lock() {
+                *last_error = Some(message.to_owned());
+            }
+        }
+        self.forward(level, message);
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    fn forward(&self, level: LogLevel, message: &str) {
+        let level = match level {
+            LogLevel::None => return,
+            LogLevel::Error => log::Level::Error,
+            LogLevel::Warning => log::Level::Warn,
+            LogLevel::Info => log::Level::Info,
+            LogLevel::Debug => log::Level::Debug,
+        };
+        match &self.label {
+            Some(label) => log::log!(target: LOG_TARGET, level, "[{}] {}", label, message),
+            None => log::log!(target: LOG_TARGET, level, "{}", message),
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    fn forward(&self, level: LogLevel, message: &str) {
+        let label = self.label.as_deref().unwrap_or("");
+        match level {
+            LogLevel::None => {}
+            LogLevel::Error => tracing::error!(target: LOG_TARGET, encoder = label, "{}", message),
+            LogLevel::Warning => tracing::warn!(target: LOG_TARGET, encoder = label, "{}", message),
+            LogLevel::Info => tracing::info!(target: LOG_TARGET, encoder = label, "{}", message),
+            LogLevel::Debug => tracing::debug!(target: LOG_TARGET, encoder = label, "{}", message),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// TRAMPOLINE
+///////////////////////////////////////////////////////////////////////////////
+
+unsafe extern "C" fn log_callback(
+    private: *mut c_void,
+    level: c_int,
+    format: *const c_char,
+    args: VaListArg,
+) {
+    if private.is_null() || format.is_null() {
+        return;
+    }
+    let context = &*(private as *const LogContext);
+    // the va_list can only be consumed once, longer messages are truncated
+    let mut buffer = [0 as c_char; 4096];
+    let written = vsnprintf(buffer.as_mut_ptr(), buffer.len(), format, args);
+    if written < 0 {
+        return;
+    }
+    let message = CStr::from_ptr(buffer.as_ptr()).to_string_lossy();
+    context.log(LogLevel::from_raw(level), &message);
+}
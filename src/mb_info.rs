@@ -0,0 +1,283 @@
+use crate::colorspace::Colorspace;
+use crate::encoder::Encoder;
+use crate::error::{Error, Result};
+use crate::picture::Picture;
+use crate::quant;
+use crate::raw;
+use crate::sys::X264ParamT;
+
+///////////////////////////////////////////////////////////////////////////////
+// MB INFO MAP
+///////////////////////////////////////////////////////////////////////////////
+
+/// Per-macroblock flags for a single frame (`prop.mb_info`).
+///
+/// Requires `analyse.b_mb_info`, see `ParamsBuilder::mb_info`. The map passed
+/// with a picture comes back with its `EncodedFrame`; if `b_mb_info_update`
+/// is set, x264 will have removed `X264_MBINFO_CONSTANT` on any macroblock
+/// whose decoded pixels have changed, e.g. to find which areas of the frame
+/// need to actually be blitted. Results are currently only set for P-frames,
+/// and may have false negatives, but no false positives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MbInfoMap {
+    mb_width: u32,
+    mb_height: u32,
+    flags: Vec<u8>,
+}
+
+impl MbInfoMap {
+    /// a map with no flags set.
+    pub fn new(mb_width: u32, mb_height: u32) -> Self {
+        MbInfoMap {
+            mb_width,
+            mb_height,
+            flags: vec![0; (mb_width * mb_height) as usize],
+        }
+    }
+
+    /// a map with no flags set covering the macroblocks of `encoder`.
+    pub fn for_encoder<T>(encoder: &Encoder<T>) -> Self {
+        let (mb_width, mb_height) = encoder.mb_grid();
+        MbInfoMap::new(mb_width, mb_height)
+    }
+
+    /// Flags as constant every macroblock whose pixels are identical in both
+    /// pictures, in all planes.
+    ///
+    /// x264 takes the flags as relative to the reference of the frame, which
+    /// is only the previous input picture without B-frames.
+    ///
+    /// `interlaced` must match the encoder (`b_interlaced` or
+    /// `b_fake_interlaced`), macroblocks are then coded in vertical pairs and
+    /// only flagged if both are constant.
+    pub fn diff(previous: &Picture, current: &Picture, interlaced: bool) -> Result<Self> {
+        if previous.csp() != current.csp()
+            || previous.width() != current.width()
+            || previous.height() != current.height()
+        {
+            return Err(Error::InvalidPicture(String::from(
+                "can only diff pictures of the same colorspace and dimensions",
+            )));
+        }
+        let csp = current.csp();
+        let colorspace = csp.colorspace;
+        let (width, height) = (current.width(), current.height());
+        let (mb_width, mb_height) = quant::mb_grid_of(width, height, interlaced);
+        let mut map = MbInfoMap::new(mb_width, mb_height);
+        let rows = height.div_ceil(16);
+        for mb_y in 0..rows {
+            for mb_x in 0..map.mb_width {
+                let (x0, x1) = (mb_x * 16, (mb_x * 16 + 16).min(width));
+                let (y0, y1) = (mb_y * 16, (mb_y * 16 + 16).min(height));
+                let constant = (0..current.plane_count()).all(|plane| {
+                    let wdiv = colorspace.width_divisor(plane);
+                    let hdiv = colorspace.height_divisor(plane);
+                    let (start, end) = if colorspace == Colorspace::V210 {
+                        // packed in groups of 6 pixels over 16 bytes
+                        ((x0 / 6 * 16) as usize, (x1.div_ceil(6) * 16) as usize)
+                    } else {
+                        // subsampled planes of odd widths are rounded down
+                        let row_bytes = csp.row_bytes(plane, width);
+                        (
+                            csp.row_bytes(plane, x0 / wdiv * wdiv).min(row_bytes),
+                            csp.row_bytes(plane, x1.div_ceil(wdiv) * wdiv).min(row_bytes),
+                        )
+                    };
+                    let rows = csp.plane_rows(plane, height);
+                    let row_start = ((y0 / hdiv) as usize).min(rows);
+                    let row_end = (y1.div_ceil(hdiv) as usize).min(rows);
+                    let (a, b) = (previous.plane(plane), current.plane(plane));
+                    let (a_stride, b_stride) = (previous.stride(plane), current.stride(plane));
+                    (row_start..row_end).all(|row| {
+                        // rows are stored bottom up in a flipped picture
+                        let row = if csp.vflip {rows - 1 - row} else {row};
+                        a[row * a_stride + start..row * a_stride + end]
+                            == b[row * b_stride + start..row * b_stride + end]
+                    })
+                });
+                map.set_constant(mb_x, mb_y, constant);
+            }
+        }
+        if interlaced {
+            for mb_y in (0..mb_height).step_by(2) {
+                for mb_x in 0..mb_width {
+                    // a row past the picture is padded from its last line,
+                    // which is in the same pair
+                    let constant = map.is_constant(mb_x, mb_y)
+                        && (mb_y + 1 >= rows || map.is_constant(mb_x, mb_y + 1));
+                    map.set_constant(mb_x, mb_y, constant);
+                    map.set_constant(mb_x, mb_y + 1, constant);
+                }
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn mb_width(&self) -> u32 {
+        self.mb_width
+    }
+
+    pub fn mb_height(&self) -> u32 {
+        self.mb_height
+    }
+
+    /// The raw `X264_MBINFO_*` flags of a macroblock.
+    pub fn get(&self, mb_x: u32, mb_y: u32) -> u8 {
+        self.flags[self.index(mb_x, mb_y)]
+    }
+
+    pub fn set(&mut self, mb_x: u32, mb_y: u32, flags: u8) {
+        let index = self.index(mb_x, mb_y);
+        self.flags[index] = flags;
+    }
+
+    /// the macroblock remains unchanged from the previous frame.
+    pub fn is_constant(&self, mb_x: u32, mb_y: u32) -> bool {
+        self.get(mb_x, mb_y) & raw::X264_MBINFO_CONSTANT as u8 != 0
+    }
+
+    pub fn set_constant(&mut self, mb_x: u32, mb_y: u32, constant: bool) {
+        let index = self.index(mb_x, mb_y);
+        if constant {
+            self.flags[index] |= raw::X264_MBINFO_CONSTANT as u8;
+        } else {
+            self.flags[index] &= !(raw::X264_MBINFO_CONSTANT as u8);
+        }
+    }
+
+    /// Number of macroblocks flagged as constant.
+    pub fn constant_count(&self) -> usize {
+        self.flags
+            .iter()
+            .filter(|x| **x & raw::X264_MBINFO_CONSTANT as u8 != 0)
+            .count()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.flags
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.flags
+    }
+
+    /// Whether the map can be used by an encoder with the given parameters.
+    pub(crate) fn check(&self, param: &X264ParamT) -> Result<()> {
+        if param.analyse.b_mb_info == 0 {
+            return Err(Error::InvalidParams(String::from("mb info requires b_mb_info")));
+        }
+        let (mb_width, mb_height) = quant::mb_grid(param);
+        if (self.mb_width, self.mb_height) != (mb_width, mb_height) {
+            return Err(Error::InvalidParams(format!(
+                "mb info map of {}x{} MBs for a {}x{} MB encoder",
+                self.mb_width, self.mb_height, mb_width, mb_height,
+            )));
+        }
+        Ok(())
+    }
+
+    /// Pointer handed to x264, which may write to it until the frame is
+    /// output if `b_mb_info_update` is set.
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.flags.as_mut_ptr()
+    }
+
+    fn index(&self, mb_x: u32, mb_y: u32) -> usize {
+        assert!(mb_x < self.mb_width && mb_y < self.mb_height, "macroblock out of range");
+        (mb_y * self.mb_width + mb_x) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colorspace::Csp;
+
+    fn picture(csp: Csp, width: u32, height: u32, buffers: &mut [Vec<u8>]) -> Picture<'_> {
+        let planes = buffers
+            .iter_mut()
+            .enumerate()
+            .map(|(i, x)| (&mut x[..], csp.row_bytes(i, width)))
+            .collect();
+        Picture::from_planes(csp, width, height, planes).unwrap()
+    }
+
+    /// the macroblocks `diff` flags as changed once the given byte of a
+    /// plane is modified, with planes of the minimum stride.
+    fn changed(
+        csp: Csp,
+        (width, height): (u32, u32),
+        (plane, row, byte): (usize, usize, usize),
+        interlaced: bool,
+    ) -> Vec<(u32, u32)> {
+        let buffers = || {
+            (0..csp.colorspace.plane_count())
+                .map(|i| vec![0; csp.row_bytes(i, width) * csp.plane_rows(i, height)])
+                .collect::<Vec<Vec<u8>>>()
+        };
+        let (mut a, mut b) = (buffers(), buffers());
+        b[plane][row * csp.row_bytes(plane, width) + byte] = 1;
+        let previous = picture(csp, width, height, &mut a);
+        let current = picture(csp, width, height, &mut b);
+        let map = MbInfoMap::diff(&previous, &current, interlaced).unwrap();
+        assert_eq!(
+            (map.mb_width(), map.mb_height()),
+            quant::mb_grid_of(width, height, interlaced),
+        );
+        let mut mbs = Vec::new();
+        for mb_y in 0..map.mb_height() {
+            for mb_x in 0..map.mb_width() {
+                if !map.is_constant(mb_x, mb_y) {
+                    mbs.push((mb_x, mb_y));
+                }
+            }
+        }
+        mbs
+    }
+
+    #[test]
+    fn planar_odd() {
+        let csp = Csp::new(Colorspace::I420);
+        assert_eq!(changed(csp, (33, 17), (0, 16, 32), false), [(2, 1)]);
+        // chroma is 16x8, its last column and row are within the first MBs
+        assert_eq!(changed(csp, (33, 17), (1, 7, 15), false), [(1, 0)]);
+        assert_eq!(changed(csp, (33, 17), (2, 0, 0), false), [(0, 0)]);
+    }
+
+    #[test]
+    fn nv12() {
+        let csp = Csp::new(Colorspace::NV12);
+        // u+v of luma column 32, row 16
+        assert_eq!(changed(csp, (64, 48), (1, 8, 33), false), [(2, 1)]);
+        assert_eq!(changed(csp, (33, 17), (1, 7, 32), false), [(2, 0)]);
+        let csp = csp.with_high_depth();
+        assert_eq!(changed(csp, (64, 48), (1, 8, 67), false), [(2, 1)]);
+    }
+
+    #[test]
+    fn v210() {
+        let csp = Csp::new(Colorspace::V210);
+        // the group of pixels 18 to 23
+        assert_eq!(changed(csp, (64, 32), (0, 20, 48), false), [(1, 1)]);
+        // the group of pixels 12 to 17, across two MBs
+        assert_eq!(changed(csp, (64, 32), (0, 20, 32), false), [(0, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn vflip() {
+        let csp = Csp::new(Colorspace::I420).with_vflip();
+        // the first row in memory is the bottom one
+        assert_eq!(changed(csp, (32, 32), (0, 0, 0), false), [(0, 1)]);
+        assert_eq!(changed(csp, (32, 32), (1, 15, 8), false), [(1, 0)]);
+    }
+
+    #[test]
+    fn interlaced() {
+        let csp = Csp::new(Colorspace::I400);
+        assert_eq!(changed(csp, (32, 64), (0, 5, 0), true), [(0, 0), (0, 1)]);
+        assert_eq!(changed(csp, (32, 64), (0, 40, 16), true), [(1, 2), (1, 3)]);
+        // 3 rows of MBs, the last pair is padded
+        assert_eq!(changed(csp, (32, 40), (0, 35, 20), true), [(1, 2), (1, 3)]);
+        assert_eq!(changed(csp, (32, 40), (0, 5, 0), true), [(0, 0), (0, 1)]);
+    }
+}
@@ -0,0 +1,187 @@
+use std::slice;
+
+use crate::raw;
+use crate::sys::X264NalT;
+
+///////////////////////////////////////////////////////////////////////////////
+// NAL ENUMS
+///////////////////////////////////////////////////////////////////////////////
+
+/// NAL unit type (`nal_unit_type_e`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NalUnitType {
+    Unknown,
+    Slice,
+    SliceDpa,
+    SliceDpb,
+    SliceDpc,
+    /// ref_idc != 0
+    SliceIdr,
+    /// ref_idc == 0
+    Sei,
+    Sps,
+    Pps,
+    Aud,
+    Filler,
+}
+
+impl NalUnitType {
+    pub fn from_raw(value: i32) -> Self {
+        match value as u32 {
+            raw::nal_unit_type_e_NAL_SLICE => NalUnitType::Slice,
+            raw::nal_unit_type_e_NAL_SLICE_DPA => NalUnitType::SliceDpa,
+            raw::nal_unit_type_e_NAL_SLICE_DPB => NalUnitType::SliceDpb,
+            raw::nal_unit_type_e_NAL_SLICE_DPC => NalUnitType::SliceDpc,
+            raw::nal_unit_type_e_NAL_SLICE_IDR => NalUnitType::SliceIdr,
+            raw::nal_unit_type_e_NAL_SEI => NalUnitType::Sei,
+            raw::nal_unit_type_e_NAL_SPS => NalUnitType::Sps,
+            raw::nal_unit_type_e_NAL_PPS => NalUnitType::Pps,
+            raw::nal_unit_type_e_NAL_AUD => NalUnitType::Aud,
+            raw::nal_unit_type_e_NAL_FILLER => NalUnitType::Filler,
+            _ => NalUnitType::Unknown,
+        }
+    }
+}
+
+/// NAL reference priority (`nal_priority_e`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NalPriority {
+    Disposable,
+    Low,
+    High,
+    Highest,
+}
+
+impl NalPriority {
+    pub fn from_raw(value: i32) -> Self {
+        match value as u32 {
+            raw::nal_priority_e_NAL_PRIORITY_LOW => NalPriority::Low,
+            raw::nal_priority_e_NAL_PRIORITY_HIGH => NalPriority::High,
+            raw::nal_priority_e_NAL_PRIORITY_HIGHEST => NalPriority::Highest,
+            _ => NalPriority::Disposable,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// NAL
+///////////////////////////////////////////////////////////////////////////////
+
+/// A NAL unit borrowed from the encoder.
+///
+/// The payload is already NAL-encapsulated: with an Annex-B startcode if
+/// `b_annexb` is set, otherwise with a 4-byte size.
+#[derive(Debug, Clone, Copy)]
+pub struct Nal<'enc> {
+    pub kind: NalUnitType,
+    pub priority: NalPriority,
+    pub payload: &'enc [u8],
+    /// If this NAL is a slice, the index of the first MB in the slice.
+    pub first_mb: u32,
+    /// If this NAL is a slice, the index of the last MB in the slice.
+    pub last_mb: u32,
+}
+
+impl<'enc> Nal<'enc> {
+    unsafe fn from_raw(nal: &'enc X264NalT) -> Self {
+        let payload = if nal.p_payload.is_null() || nal.i_payload <= 0 {
+            &[][..]
+        } else {
+            slice::from_raw_parts(nal.p_payload, nal.i_payload as usize)
+        };
+        Nal {
+            kind: NalUnitType::from_raw(nal.i_type),
+            priority: NalPriority::from_raw(nal.i_ref_idc),
+            payload,
+            first_mb: nal.i_first_mb.max(0) as u32,
+            last_mb: nal.i_last_mb.max(0) as u32,
+        }
+    }
+
+    pub fn into_owned(self) -> OwnedNal {
+        OwnedNal {
+            kind: self.kind,
+            priority: self.priority,
+            payload: self.payload.to_vec(),
+            first_mb: self.first_mb,
+            last_mb: self.last_mb,
+        }
+    }
+}
+
+/// A NAL unit copied out of the encoder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedNal {
+    pub kind: NalUnitType,
+    pub priority: NalPriority,
+    pub payload: Vec<u8>,
+    pub first_mb: u32,
+    pub last_mb: u32,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// NALS
+///////////////////////////////////////////////////////////////////////////////
+
+/// The NAL units returned by a single call to `encode` or `headers`.
+///
+/// All data returned by x264, including the payloads, is no longer valid after
+/// the next call into the encoder. The mutable borrow of the encoder held by
+/// this iterator is what prevents such use; copy the NALs out with
+/// `into_owned` to keep them around.
+#[derive(Debug, Clone)]
+pub struct Nals<'enc> {
+    iter: slice::Iter<'enc, X264NalT>,
+}
+
+impl<'enc> Nals<'enc> {
+    /// # Safety
+    ///
+    /// `nals` and the payloads it points to must stay valid for `'enc`.
+    pub(crate) unsafe fn from_raw(nals: &'enc [X264NalT]) -> Self {
+        Nals {iter: nals.iter()}
+    }
+
+    pub(crate) fn empty() -> Self {
+        Nals {iter: [].iter()}
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter.len() == 0
+    }
+
+    /// The payloads of the remaining NALs, which x264 guarantees are
+    /// sequential in memory, as a single slice.
+    pub fn as_bytes(&self) -> &'enc [u8] {
+        let nals = self.iter.as_slice();
+        match nals.first() {
+            None => &[],
+            Some(first) if first.p_payload.is_null() => &[],
+            Some(first) => {
+                let len = nals
+                    .iter()
+                    .map(|x| x.i_payload.max(0) as usize)
+                    .sum();
+                unsafe { slice::from_raw_parts(first.p_payload, len) }
+            }
+        }
+    }
+
+    pub fn into_owned(self) -> Vec<OwnedNal> {
+        self.map(Nal::into_owned).collect()
+    }
+}
+
+impl<'enc> Iterator for Nals<'enc> {
+    type Item = Nal<'enc>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|x| unsafe { Nal::from_raw(x) })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'enc> ExactSizeIterator for Nals<'enc> {}
@@ -0,0 +1,215 @@
+//! The name tables of `x264.h`.
+//!
+//! The header defines these as `static const` arrays, so every translation
+//! unit gets a private copy and none is exported by libx264; the `static mut`
+//! declarations bindgen emits for them can not be linked. The tables are
+//! mirrored here instead, in the same order, without the terminating null.
+//!
+//! Entries are indexed by the value x264 stores in `X264ParamT`, which is why
+//! some tables hold empty strings for reserved values.
+
+///////////////////////////////////////////////////////////////////////////////
+// TABLES
+///////////////////////////////////////////////////////////////////////////////
+
+const DIRECT_PRED_NAMES: &[&str] = &["none", "spatial", "temporal", "auto"];
+const MOTION_EST_NAMES: &[&str] = &["dia", "hex", "umh", "esa", "tesa"];
+const B_PYRAMID_NAMES: &[&str] = &["none", "strict", "normal"];
+const OVERSCAN_NAMES: &[&str] = &["undef", "show", "crop"];
+const VIDFORMAT_NAMES: &[&str] = &["component", "pal", "ntsc", "secam", "mac", "undef"];
+const FULLRANGE_NAMES: &[&str] = &["off", "on"];
+const COLORPRIM_NAMES: &[&str] = &[
+    "", "bt709", "undef", "", "bt470m", "bt470bg", "smpte170m", "smpte240m", "film", "bt2020",
+    "smpte428", "smpte431", "smpte432",
+];
+const TRANSFER_NAMES: &[&str] = &[
+    "", "bt709", "undef", "", "bt470m", "bt470bg", "smpte170m", "smpte240m", "linear", "log100",
+    "log316", "iec61966-2-4", "bt1361e", "iec61966-2-1", "bt2020-10", "bt2020-12", "smpte2084",
+    "smpte428", "arib-std-b67",
+];
+const COLMATRIX_NAMES: &[&str] = &[
+    "GBR", "bt709", "undef", "", "fcc", "bt470bg", "smpte170m", "smpte240m", "YCgCo", "bt2020nc",
+    "bt2020c", "smpte2085", "chroma-derived-nc", "chroma-derived-c", "ICtCp",
+];
+const NAL_HRD_NAMES: &[&str] = &["none", "vbr", "cbr"];
+const AVCINTRA_FLAVOR_NAMES: &[&str] = &["panasonic", "sony"];
+const PRESET_NAMES: &[&str] = &[
+    "ultrafast", "superfast", "veryfast", "faster", "fast", "medium", "slow", "slower",
+    "veryslow", "placebo",
+];
+const TUNE_NAMES: &[&str] = &[
+    "film", "animation", "grain", "stillimage", "psnr", "ssim", "fastdecode", "zerolatency",
+];
+const PROFILE_NAMES: &[&str] = &["baseline", "main", "high", "high10", "high422", "high444"];
+
+/// `x264_direct_pred_names`
+pub fn direct_pred_names() -> &'static [&'static str] {DIRECT_PRED_NAMES}
+/// `x264_motion_est_names`
+pub fn motion_est_names() -> &'static [&'static str] {MOTION_EST_NAMES}
+/// `x264_b_pyramid_names`
+pub fn b_pyramid_names() -> &'static [&'static str] {B_PYRAMID_NAMES}
+/// `x264_overscan_names`
+pub fn overscan_names() -> &'static [&'static str] {OVERSCAN_NAMES}
+/// `x264_vidformat_names`
+pub fn vidformat_names() -> &'static [&'static str] {VIDFORMAT_NAMES}
+/// `x264_fullrange_names`
+pub fn fullrange_names() -> &'static [&'static str] {FULLRANGE_NAMES}
+/// `x264_colorprim_names`
+pub fn colorprim_names() -> &'static [&'static str] {COLORPRIM_NAMES}
+/// `x264_transfer_names`
+pub fn transfer_names() -> &'static [&'static str] {TRANSFER_NAMES}
+/// `x264_colmatrix_names`
+pub fn colmatrix_names() -> &'static [&'static str] {COLMATRIX_NAMES}
+/// `x264_nal_hrd_names`
+pub fn nal_hrd_names() -> &'static [&'static str] {NAL_HRD_NAMES}
+/// `x264_avcintra_flavor_names`
+pub fn avcintra_flavor_names() -> &'static [&'static str] {AVCINTRA_FLAVOR_NAMES}
+/// `x264_preset_names`
+pub fn preset_names() -> &'static [&'static str] {PRESET_NAMES}
+/// `x264_tune_names`, the psy tunings followed by fastdecode and zerolatency.
+pub fn tune_names() -> &'static [&'static str] {TUNE_NAMES}
+/// `x264_profile_names`
+pub fn profile_names() -> &'static [&'static str] {PROFILE_NAMES}
+
+/// Index of `name` in `table`, compared case-insensitively like x264's own
+/// option parser. Reserved (empty) entries never match.
+pub(crate) fn find(table: &[&str], name: &str) -> Option<usize> {
+    table
+        .iter()
+        .position(|x| !x.is_empty() && x.eq_ignore_ascii_case(name))
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ENUM IMPLS
+///////////////////////////////////////////////////////////////////////////////
+
+/// Implements `ALL`, `as_str`, `Display` and `FromStr` for an enum whose
+/// discriminants index the given name table. Parse errors are reported as a
+/// bad value for the x264 option of the same name.
+macro_rules! impl_names {
+    ($ty:ident, $table:path, $option:expr, [$($variant:ident),* $(,)?]) => {
+        impl $ty {
+            pub const ALL: &'static [$ty] = &[$($ty::$variant),*];
+
+            pub fn as_str(self) -> &'static str {
+                $table()[self as usize]
+            }
+        }
+
+        impl std::fmt::Display for $ty {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl std::str::FromStr for $ty {
+            type Err = crate::error::Error;
+
+            fn from_str(s: &str) -> crate::error::Result<Self> {
+                crate::names::find($table(), s)
+                    .and_then(|ix| $ty::ALL.iter().find(|x| **x as usize == ix))
+                    .cloned()
+                    .ok_or_else(|| crate::error::Error::BadParamValue {
+                        name: $option.to_owned(),
+                        value: Some(s.to_owned()),
+                    })
+            }
+        }
+    };
+}
+
+pub(crate) use impl_names;
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+    use std::str::FromStr;
+
+    use crate::error::Error;
+    use crate::params::*;
+    use crate::vui::*;
+
+    /// every variant parses back from its name, and reserved entries of
+    /// `table` parse to nothing.
+    fn round_trip<T>(all: &[T], table: &[&str])
+    where
+        T: Copy + Debug + PartialEq + ToString + FromStr<Err = Error>,
+    {
+        for x in all {
+            let name = x.to_string();
+            assert!(!name.is_empty(), "{:?}", x);
+            assert_eq!(name.parse::<T>(), Ok(*x));
+            assert_eq!(name.to_uppercase().parse::<T>(), Ok(*x));
+        }
+        for name in table.iter().filter(|x| x.is_empty()) {
+            assert!(name.parse::<T>().is_err());
+        }
+        assert!("bogus".parse::<T>().is_err());
+    }
+
+    #[test]
+    fn preset() {
+        round_trip(Preset::ALL, super::preset_names());
+    }
+
+    #[test]
+    fn tune() {
+        round_trip(PsyTune::ALL, super::tune_names());
+        assert!("zerolatency".parse::<PsyTune>().is_err());
+    }
+
+    #[test]
+    fn profile() {
+        round_trip(Profile::ALL, super::profile_names());
+    }
+
+    #[test]
+    fn motion_est() {
+        round_trip(MotionEstimation::ALL, super::motion_est_names());
+    }
+
+    #[test]
+    fn b_pyramid() {
+        round_trip(BPyramid::ALL, super::b_pyramid_names());
+    }
+
+    #[test]
+    fn direct_pred() {
+        round_trip(DirectPrediction::ALL, super::direct_pred_names());
+    }
+
+    #[test]
+    fn nal_hrd() {
+        round_trip(NalHrd::ALL, super::nal_hrd_names());
+    }
+
+    #[test]
+    fn avcintra_flavor() {
+        round_trip(AvcIntraFlavor::ALL, super::avcintra_flavor_names());
+    }
+
+    #[test]
+    fn overscan() {
+        round_trip(Overscan::ALL, super::overscan_names());
+    }
+
+    #[test]
+    fn vidformat() {
+        round_trip(VideoFormat::ALL, super::vidformat_names());
+    }
+
+    #[test]
+    fn colorprim() {
+        round_trip(ColorPrimaries::ALL, super::colorprim_names());
+    }
+
+    #[test]
+    fn transfer() {
+        round_trip(TransferCharacteristics::ALL, super::transfer_names());
+    }
+
+    #[test]
+    fn colmatrix() {
+        round_trip(MatrixCoefficients::ALL, super::colmatrix_names());
+    }
+}
@@ -0,0 +1,1133 @@
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::os::raw::{c_int, c_uint};
+use std::ptr;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+use crate::hdr::ColorMetadata;
+use crate::level::Level;
+use crate::logging::LogLevel;
+use crate::names::{self, impl_names};
+use crate::raw;
+use crate::sys::{self, X264ParamT};
+use crate::vui::Vui;
+use crate::zone::{self, Zone};
+
+
+///////////////////////////////////////////////////////////////////////////////
+// PRESETS, TUNES & PROFILES
+///////////////////////////////////////////////////////////////////////////////
+
+/// Encoder presets, ordered from fastest to slowest.
+///
+/// Warning: the speed of these presets scales dramatically. Ultrafast is a full
+/// 100 times faster than placebo!
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Preset {
+    Ultrafast,
+    Superfast,
+    Veryfast,
+    Faster,
+    Fast,
+    #[default]
+    Medium,
+    Slow,
+    Slower,
+    Veryslow,
+    Placebo,
+}
+
+impl_names!(Preset, names::preset_names, "preset", [
+    Ultrafast, Superfast, Veryfast, Faster, Fast, Medium, Slow, Slower, Veryslow, Placebo,
+]);
+
+/// The psy tunings, of which at most one may be used at a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PsyTune {
+    Film,
+    Animation,
+    Grain,
+    StillImage,
+    Psnr,
+    Ssim,
+}
+
+impl_names!(PsyTune, names::tune_names, "tune", [
+    Film, Animation, Grain, StillImage, Psnr, Ssim,
+]);
+
+/// A combination of tunings.
+///
+/// Multiple tunings can be used, however multiple psy tunings cannot, which is
+/// why there is room for only one `PsyTune` here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Tune {
+    pub psy: Option<PsyTune>,
+    pub fast_decode: bool,
+    pub zero_latency: bool,
+}
+
+impl Tune {
+    pub fn psy(tune: PsyTune) -> Self {
+        Tune {psy: Some(tune), ..Tune::default()}
+    }
+    pub fn with_fast_decode(self) -> Self {
+        Tune {fast_decode: true, ..self}
+    }
+    pub fn with_zero_latency(self) -> Self {
+        Tune {zero_latency: true, ..self}
+    }
+    /// The tunings as passed to x264_param_default_preset, e.g. "film,zerolatency".
+    pub fn to_tune_string(&self) -> Option<String> {
+        let names = self.psy
+            .map(PsyTune::as_str)
+            .into_iter()
+            .chain(if self.fast_decode {Some("fastdecode")} else {None})
+            .chain(if self.zero_latency {Some("zerolatency")} else {None})
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            None
+        } else {
+            Some(names.join(","))
+        }
+    }
+}
+
+impl fmt::Display for Tune {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_tune_string().unwrap_or_default())
+    }
+}
+
+/// Parses tunings separated by any of ",./-+", like x264_param_default_preset.
+impl FromStr for Tune {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bad_value = || Error::BadParamValue {
+            name: "tune".to_owned(),
+            value: Some(s.to_owned()),
+        };
+        let mut tune = Tune::default();
+        for name in s.split(|c| ",./-+".contains(c)).filter(|x| !x.is_empty()) {
+            match names::find(names::tune_names(), name).map(|ix| names::tune_names()[ix]) {
+                Some("fastdecode") => tune.fast_decode = true,
+                Some("zerolatency") => tune.zero_latency = true,
+                Some(_) if tune.psy.is_some() => return Err(bad_value()),
+                Some(_) => tune.psy = Some(name.parse()?),
+                None => return Err(bad_value()),
+            }
+        }
+        Ok(tune)
+    }
+}
+
+/// Profiles, from most to least restrictive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Profile {
+    Baseline,
+    Main,
+    High,
+    High10,
+    High422,
+    High444,
+}
+
+impl_names!(Profile, names::profile_names, "profile", [
+    Baseline, Main, High, High10, High422, High444,
+]);
+
+///////////////////////////////////////////////////////////////////////////////
+// OPTION ENUMS
+///////////////////////////////////////////////////////////////////////////////
+
+/// Chroma format of the encoded bitstream (`i_csp`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChromaFormat {
+    I400 = raw::X264_CSP_I400 as isize,
+    I420 = raw::X264_CSP_I420 as isize,
+    I422 = raw::X264_CSP_I422 as isize,
+    I444 = raw::X264_CSP_I444 as isize,
+}
+
+/// Rate control method (`rc.i_rc_method`) along with its main parameter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateControl {
+    /// constant quantizer, 0 is lossless.
+    Cqp(u8),
+    /// constant rate factor, i.e. 1pass VBR with a nominal quality.
+    Crf(f32),
+    /// average bitrate in kbit/sec.
+    Abr(u32),
+}
+
+/// Motion estimation algorithm (`analyse.i_me_method`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MotionEstimation {
+    Dia = raw::X264_ME_DIA as isize,
+    Hex = raw::X264_ME_HEX as isize,
+    Umh = raw::X264_ME_UMH as isize,
+    Esa = raw::X264_ME_ESA as isize,
+    Tesa = raw::X264_ME_TESA as isize,
+}
+
+impl_names!(MotionEstimation, names::motion_est_names, "me", [Dia, Hex, Umh, Esa, Tesa]);
+
+/// Keep some B-frames as references (`i_bframe_pyramid`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BPyramid {
+    None = raw::X264_B_PYRAMID_NONE as isize,
+    /// strictly hierarchical pyramid, Blu-ray compatible.
+    Strict = raw::X264_B_PYRAMID_STRICT as isize,
+    Normal = raw::X264_B_PYRAMID_NORMAL as isize,
+}
+
+impl_names!(BPyramid, names::b_pyramid_names, "b-pyramid", [None, Strict, Normal]);
+
+/// Adaptive B-frame decision (`i_bframe_adaptive`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BAdapt {
+    None = raw::X264_B_ADAPT_NONE as isize,
+    Fast = raw::X264_B_ADAPT_FAST as isize,
+    Trellis = raw::X264_B_ADAPT_TRELLIS as isize,
+}
+
+/// Weighting for P-frames (`analyse.i_weighted_pred`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WeightedPrediction {
+    None = raw::X264_WEIGHTP_NONE as isize,
+    Simple = raw::X264_WEIGHTP_SIMPLE as isize,
+    Smart = raw::X264_WEIGHTP_SMART as isize,
+}
+
+/// Direct MV prediction mode (`analyse.i_direct_mv_pred`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DirectPrediction {
+    None = raw::X264_DIRECT_PRED_NONE as isize,
+    Spatial = raw::X264_DIRECT_PRED_SPATIAL as isize,
+    Temporal = raw::X264_DIRECT_PRED_TEMPORAL as isize,
+    Auto = raw::X264_DIRECT_PRED_AUTO as isize,
+}
+
+impl_names!(DirectPrediction, names::direct_pred_names, "direct", [
+    None, Spatial, Temporal, Auto,
+]);
+
+/// Psy adaptive QP (`rc.i_aq_mode`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AdaptiveQuant {
+    None = raw::X264_AQ_NONE as isize,
+    Variance = raw::X264_AQ_VARIANCE as isize,
+    AutoVariance = raw::X264_AQ_AUTOVARIANCE as isize,
+    AutoVarianceBiased = raw::X264_AQ_AUTOVARIANCE_BIASED as isize,
+}
+
+/// Trellis RD quantization (`analyse.i_trellis`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Trellis {
+    Off = 0,
+    /// only on the final encode of a macroblock.
+    FinalMb = 1,
+    /// on all mode decisions.
+    All = 2,
+}
+
+/// Custom quant matrices preset (`i_cqm_preset`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Cqm {
+    Flat = raw::X264_CQM_FLAT as isize,
+    Jvt = raw::X264_CQM_JVT as isize,
+}
+
+/// NAL HRD signaling (`i_nal_hrd`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NalHrd {
+    None = raw::X264_NAL_HRD_NONE as isize,
+    Vbr = raw::X264_NAL_HRD_VBR as isize,
+    Cbr = raw::X264_NAL_HRD_CBR as isize,
+}
+
+impl_names!(NalHrd, names::nal_hrd_names, "nal-hrd", [None, Vbr, Cbr]);
+
+/// AVC-Intra flavor (`i_avcintra_flavor`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AvcIntraFlavor {
+    Panasonic = raw::X264_AVCINTRA_FLAVOR_PANASONIC as isize,
+    Sony = raw::X264_AVCINTRA_FLAVOR_SONY as isize,
+}
+
+impl_names!(AvcIntraFlavor, names::avcintra_flavor_names, "avcintra-flavor", [
+    Panasonic, Sony,
+]);
+
+/// Macroblock partitions to analyse (`analyse.intra` & `analyse.inter`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Partitions {
+    pub i4x4: bool,
+    /// requires the 8x8 transform.
+    pub i8x8: bool,
+    /// p16x8, p8x16 and p8x8.
+    pub p8x8: bool,
+    /// p8x4, p4x8, p4x4.
+    pub p4x4: bool,
+    /// b16x8, b8x16 and b8x8.
+    pub b8x8: bool,
+}
+
+impl Partitions {
+    pub(crate) fn to_flags(self) -> c_uint {
+        let mut flags = 0;
+        if self.i4x4 {flags |= raw::X264_ANALYSE_I4x4}
+        if self.i8x8 {flags |= raw::X264_ANALYSE_I8x8}
+        if self.p8x8 {flags |= raw::X264_ANALYSE_PSUB16x16}
+        if self.p4x4 {flags |= raw::X264_ANALYSE_PSUB8x8}
+        if self.b8x8 {flags |= raw::X264_ANALYSE_BSUB16x16}
+        flags
+    }
+}
+
+/// Cropping rectangle (`crop_rect`), added to those implicitly defined by
+/// non-mod16 video resolutions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct CropRect {
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PARAMS
+///////////////////////////////////////////////////////////////////////////////
+
+/// A validated, ready to use `X264ParamT`.
+#[derive(Clone)]
+pub struct Params {
+    raw: X264ParamT,
+    log_label: Option<String>,
+    color: Option<ColorMetadata>,
+    zones: Vec<Zone>,
+}
+
+impl Params {
+    pub fn builder() -> ParamsBuilder {
+        ParamsBuilder::default()
+    }
+
+    /// Wrap an existing parameter struct without any validation.
+    ///
+    /// # Safety
+    ///
+    /// Every pointer carried by `raw` (filenames, zones, callbacks) must remain
+    /// valid for as long as the returned value is used.
+    pub unsafe fn from_raw(raw: X264ParamT) -> Params {
+        Params {raw, log_label: None, color: None, zones: Vec::new()}
+    }
+
+    pub fn as_raw(&self) -> &X264ParamT {
+        &self.raw
+    }
+
+    /// # Safety
+    ///
+    /// Same requirements as `from_raw` for any pointer written through the
+    /// returned reference.
+    pub unsafe fn as_raw_mut(&mut self) -> &mut X264ParamT {
+        &mut self.raw
+    }
+
+    /// set one parameter by name.
+    ///
+    /// note: a bad value is only reported if it can't even be parsed, numerical
+    /// range is not checked until the encoder is opened or reconfigured.
+    pub fn parse(&mut self, name: &str, value: Option<&str>) -> Result<()> {
+        parse_option(&mut self.raw, name, value)
+    }
+
+    pub fn log_label(&self) -> Option<&str> {
+        self.log_label.as_deref()
+    }
+
+    pub fn set_log_label(&mut self, label: Option<String>) {
+        self.log_label = label;
+    }
+
+    /// the colour description set with `ParamsBuilder::color_metadata`.
+    pub fn color_metadata(&self) -> Option<&ColorMetadata> {
+        self.color.as_ref()
+    }
+
+    /// the zones set with `ParamsBuilder::zones` or the "zones" option.
+    pub fn zones(&self) -> &[Zone] {
+        &self.zones
+    }
+
+    pub fn width(&self) -> u32 {
+        self.raw.i_width as u32
+    }
+
+    pub fn height(&self) -> u32 {
+        self.raw.i_height as u32
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PARAMS BUILDER
+///////////////////////////////////////////////////////////////////////////////
+
+type Setter = Box<dyn FnOnce(&mut X264ParamT) -> Result<()> + Send>;
+type Check = fn(&X264ParamT) -> Result<()>;
+
+/// Builds `Params` in the order x264CLI does:
+///
+/// 1) x264_param_default_preset
+/// 2) the custom options set on this builder, in call order
+/// 3) x264_param_apply_profile
+///
+/// The result is validated between steps 2 and 3.
+#[derive(Default)]
+pub struct ParamsBuilder {
+    preset: Preset,
+    tune: Tune,
+    profile: Option<Profile>,
+    log_label: Option<String>,
+    color: Option<ColorMetadata>,
+    zones: Vec<Zone>,
+    setters: Vec<Setter>,
+    checks: Vec<Check>,
+}
+
+impl ParamsBuilder {
+    fn set(mut self, f: impl FnOnce(&mut X264ParamT) + Send + 'static) -> Self {
+        self.setters.push(Box::new(move |p| {
+            f(p);
+            Ok(())
+        }));
+        self
+    }
+
+    /// set one parameter by name, as x264CLI would, e.g. `("keyint", Some("250"))`.
+    ///
+    /// `None` means "true" for boolean options, but is a bad value for others.
+    /// Options are applied in call order along with the typed setters.
+    pub fn option(mut self, name: &str, value: Option<&str>) -> Self {
+        let name = name.to_owned();
+        let value = value.map(ToOwned::to_owned);
+        self.setters.push(Box::new(move |p| parse_option(p, &name, value.as_deref())));
+        self
+    }
+
+    /// reject the built parameters if `check` fails, once the profile was
+    /// applied, whichever options are set afterwards.
+    pub(crate) fn check(mut self, check: Check) -> Self {
+        self.checks.push(check);
+        self
+    }
+
+    pub fn preset(self, preset: Preset) -> Self {
+        ParamsBuilder {preset, ..self}
+    }
+    pub fn tune(self, tune: Tune) -> Self {
+        ParamsBuilder {tune, ..self}
+    }
+    pub fn profile(self, profile: Profile) -> Self {
+        ParamsBuilder {profile: Some(profile), ..self}
+    }
+
+    // LOGGING
+
+    pub fn log_level(self, level: LogLevel) -> Self {
+        self.set(move |p| p.i_log_level = level as c_int)
+    }
+    /// label attached to every record the encoder logs, e.g. a stream id.
+    pub fn log_label(self, label: impl Into<String>) -> Self {
+        ParamsBuilder {log_label: Some(label.into()), ..self}
+    }
+
+    // THREADING
+
+    /// encode multiple frames in parallel, 0 is auto.
+    pub fn threads(self, threads: u32) -> Self {
+        self.set(move |p| p.i_threads = threads as c_int)
+    }
+    pub fn lookahead_threads(self, threads: u32) -> Self {
+        self.set(move |p| p.i_lookahead_threads = threads as c_int)
+    }
+    pub fn sliced_threads(self, enabled: bool) -> Self {
+        self.set(move |p| p.b_sliced_threads = enabled as c_int)
+    }
+    pub fn deterministic(self, enabled: bool) -> Self {
+        self.set(move |p| p.b_deterministic = enabled as c_int)
+    }
+    pub fn cpu_independent(self, enabled: bool) -> Self {
+        self.set(move |p| p.b_cpu_independent = enabled as c_int)
+    }
+    /// threaded lookahead buffer, `None` is auto.
+    pub fn sync_lookahead(self, frames: Option<u32>) -> Self {
+        let value = frames.map(|x| x as c_int).unwrap_or(raw::X264_SYNC_LOOKAHEAD_AUTO);
+        self.set(move |p| p.i_sync_lookahead = value)
+    }
+
+    // VIDEO PROPERTIES
+
+    pub fn size(self, width: u32, height: u32) -> Self {
+        self.set(move |p| {
+            p.i_width = width as c_int;
+            p.i_height = height as c_int;
+        })
+    }
+    pub fn chroma_format(self, format: ChromaFormat) -> Self {
+        self.set(move |p| p.i_csp = format as c_int)
+    }
+    pub fn bit_depth(self, depth: u8) -> Self {
+        self.set(move |p| p.i_bitdepth = depth as c_int)
+    }
+    pub fn level_idc(self, level_idc: u8) -> Self {
+        self.set(move |p| p.i_level_idc = level_idc as c_int)
+    }
+    /// target level, checked against the rest of the parameters on `build`.
+    pub fn level(self, level: Level) -> Self {
+        self.level_idc(level.level_idc())
+    }
+    /// number of frames to encode if known.
+    pub fn frame_total(self, frames: u32) -> Self {
+        self.set(move |p| p.i_frame_total = frames as c_int)
+    }
+    pub fn nal_hrd(self, hrd: NalHrd) -> Self {
+        self.set(move |p| p.i_nal_hrd = hrd as c_int)
+    }
+    pub fn vui(self, vui: Vui) -> Self {
+        self.set(move |p| vui.apply(p))
+    }
+    /// override ratecontrol or other options for ranges of frames, taking
+    /// precedence over the "zones" option.
+    ///
+    /// zones are checked not to overlap, nor to go past `frame_total` if set.
+    pub fn zones(self, zones: Vec<Zone>) -> Self {
+        ParamsBuilder {zones, ..self}
+    }
+    /// colour fields of the VUI, plus the SEI of HDR streams written by the
+    /// encoder, see `ColorMetadata`.
+    pub fn color_metadata(mut self, color: ColorMetadata) -> Self {
+        self.color = Some(color);
+        self.setters.push(Box::new(move |p| {
+            color.check()?;
+            color.apply(p);
+            Ok(())
+        }));
+        self
+    }
+    pub fn crop(self, rect: CropRect) -> Self {
+        self.set(move |p| {
+            p.crop_rect.i_left = rect.left;
+            p.crop_rect.i_top = rect.top;
+            p.crop_rect.i_right = rect.right;
+            p.crop_rect.i_bottom = rect.bottom;
+        })
+    }
+
+    // BITSTREAM
+
+    /// maximum number of reference frames.
+    pub fn frame_reference(self, frames: u8) -> Self {
+        self.set(move |p| p.i_frame_reference = frames as c_int)
+    }
+    /// force a DPB size larger than that implied by B-frames and reference frames.
+    pub fn dpb_size(self, frames: u8) -> Self {
+        self.set(move |p| p.i_dpb_size = frames as c_int)
+    }
+    /// force an IDR keyframe at this interval, `None` for no limit.
+    pub fn keyint_max(self, frames: Option<u32>) -> Self {
+        let value = frames.unwrap_or(raw::X264_KEYINT_MAX_INFINITE) as c_int;
+        self.set(move |p| p.i_keyint_max = value)
+    }
+    /// scenecuts closer together than this are coded as I, not IDR, `None` is auto.
+    pub fn keyint_min(self, frames: Option<u32>) -> Self {
+        let value = frames.unwrap_or(raw::X264_KEYINT_MIN_AUTO) as c_int;
+        self.set(move |p| p.i_keyint_min = value)
+    }
+    /// how aggressively to insert extra I frames, 0 disables scenecut detection.
+    pub fn scenecut_threshold(self, threshold: u32) -> Self {
+        self.set(move |p| p.i_scenecut_threshold = threshold as c_int)
+    }
+    /// use periodic intra refresh instead of IDR frames.
+    pub fn intra_refresh(self, enabled: bool) -> Self {
+        self.set(move |p| p.b_intra_refresh = enabled as c_int)
+    }
+    /// how many B-frames between 2 reference pictures.
+    pub fn bframes(self, frames: u8) -> Self {
+        self.set(move |p| p.i_bframe = frames as c_int)
+    }
+    pub fn b_adapt(self, mode: BAdapt) -> Self {
+        self.set(move |p| p.i_bframe_adaptive = mode as c_int)
+    }
+    pub fn b_bias(self, bias: i32) -> Self {
+        self.set(move |p| p.i_bframe_bias = bias)
+    }
+    pub fn b_pyramid(self, mode: BPyramid) -> Self {
+        self.set(move |p| p.i_bframe_pyramid = mode as c_int)
+    }
+    pub fn open_gop(self, enabled: bool) -> Self {
+        self.set(move |p| p.b_open_gop = enabled as c_int)
+    }
+    pub fn bluray_compat(self, enabled: bool) -> Self {
+        self.set(move |p| p.b_bluray_compat = enabled as c_int)
+    }
+    /// AVC-Intra class (50, 100 or 200), 0 disables.
+    pub fn avcintra(self, class: u32, flavor: AvcIntraFlavor) -> Self {
+        self.set(move |p| {
+            p.i_avcintra_class = class as c_int;
+            p.i_avcintra_flavor = flavor as c_int;
+        })
+    }
+    /// loop filter as (alpha c0, beta) offsets in `-6..=6`, `None` disables it.
+    pub fn deblock(self, strength: Option<(i8, i8)>) -> Self {
+        self.set(move |p| match strength {
+            Some((alpha, beta)) => {
+                p.b_deblocking_filter = 1;
+                p.i_deblocking_filter_alphac0 = alpha as c_int;
+                p.i_deblocking_filter_beta = beta as c_int;
+            }
+            None => {
+                p.b_deblocking_filter = 0;
+            }
+        })
+    }
+    pub fn cabac(self, enabled: bool) -> Self {
+        self.set(move |p| p.b_cabac = enabled as c_int)
+    }
+    pub fn cabac_init_idc(self, idc: u8) -> Self {
+        self.set(move |p| p.i_cabac_init_idc = idc as c_int)
+    }
+    /// interlaced encoding, `Some(true)` for top field first.
+    pub fn interlaced(self, tff: Option<bool>) -> Self {
+        self.set(move |p| {
+            p.b_interlaced = tff.is_some() as c_int;
+            p.b_tff = tff.unwrap_or(false) as c_int;
+        })
+    }
+    /// flag the stream as PAFF interlaced yet encode all frames progressively.
+    pub fn fake_interlaced(self, enabled: bool) -> Self {
+        self.set(move |p| p.b_fake_interlaced = enabled as c_int)
+    }
+    pub fn constrained_intra(self, enabled: bool) -> Self {
+        self.set(move |p| p.b_constrained_intra = enabled as c_int)
+    }
+    pub fn cqm(self, cqm: Cqm) -> Self {
+        self.set(move |p| p.i_cqm_preset = cqm as c_int)
+    }
+    /// fully reconstruct frames, even when not necessary for encoding.
+    pub fn full_recon(self, enabled: bool) -> Self {
+        self.set(move |p| p.b_full_recon = enabled as c_int)
+    }
+
+    // ANALYSE
+
+    pub fn intra_partitions(self, partitions: Partitions) -> Self {
+        self.set(move |p| p.analyse.intra = partitions.to_flags())
+    }
+    pub fn inter_partitions(self, partitions: Partitions) -> Self {
+        self.set(move |p| p.analyse.inter = partitions.to_flags())
+    }
+    pub fn transform_8x8(self, enabled: bool) -> Self {
+        self.set(move |p| p.analyse.b_transform_8x8 = enabled as c_int)
+    }
+    pub fn weighted_prediction(self, mode: WeightedPrediction) -> Self {
+        self.set(move |p| p.analyse.i_weighted_pred = mode as c_int)
+    }
+    /// implicit weighting for B-frames.
+    pub fn weighted_bipred(self, enabled: bool) -> Self {
+        self.set(move |p| p.analyse.b_weighted_bipred = enabled as c_int)
+    }
+    pub fn direct_prediction(self, mode: DirectPrediction) -> Self {
+        self.set(move |p| p.analyse.i_direct_mv_pred = mode as c_int)
+    }
+    pub fn chroma_qp_offset(self, offset: i8) -> Self {
+        self.set(move |p| p.analyse.i_chroma_qp_offset = offset as c_int)
+    }
+    pub fn motion_estimation(self, method: MotionEstimation) -> Self {
+        self.set(move |p| p.analyse.i_me_method = method as c_int)
+    }
+    /// integer pixel motion estimation search range (from predicted mv).
+    pub fn me_range(self, range: u32) -> Self {
+        self.set(move |p| p.analyse.i_me_range = range as c_int)
+    }
+    /// maximum length of a mv (in pixels), `None` is auto, based on level.
+    pub fn mv_range(self, range: Option<u32>) -> Self {
+        let value = range.map(|x| x as c_int).unwrap_or(-1);
+        self.set(move |p| p.analyse.i_mv_range = value)
+    }
+    /// minimum space between threads, `None` is auto, based on number of threads.
+    pub fn mv_range_thread(self, range: Option<u32>) -> Self {
+        let value = range.map(|x| x as c_int).unwrap_or(-1);
+        self.set(move |p| p.analyse.i_mv_range_thread = value)
+    }
+    /// subpixel motion estimation quality, in `0..=11`.
+    pub fn subpel_refine(self, level: u8) -> Self {
+        self.set(move |p| p.analyse.i_subpel_refine = level as c_int)
+    }
+    pub fn chroma_me(self, enabled: bool) -> Self {
+        self.set(move |p| p.analyse.b_chroma_me = enabled as c_int)
+    }
+    pub fn mixed_references(self, enabled: bool) -> Self {
+        self.set(move |p| p.analyse.b_mixed_references = enabled as c_int)
+    }
+    pub fn trellis(self, mode: Trellis) -> Self {
+        self.set(move |p| p.analyse.i_trellis = mode as c_int)
+    }
+    pub fn fast_pskip(self, enabled: bool) -> Self {
+        self.set(move |p| p.analyse.b_fast_pskip = enabled as c_int)
+    }
+    pub fn dct_decimate(self, enabled: bool) -> Self {
+        self.set(move |p| p.analyse.b_dct_decimate = enabled as c_int)
+    }
+    pub fn noise_reduction(self, strength: u32) -> Self {
+        self.set(move |p| p.analyse.i_noise_reduction = strength as c_int)
+    }
+    /// psy RD and psy trellis strengths.
+    pub fn psy_rd(self, rd: f32, trellis: f32) -> Self {
+        self.set(move |p| {
+            p.analyse.f_psy_rd = rd;
+            p.analyse.f_psy_trellis = trellis;
+        })
+    }
+    /// toggle all psy optimizations.
+    pub fn psy(self, enabled: bool) -> Self {
+        self.set(move |p| p.analyse.b_psy = enabled as c_int)
+    }
+    /// luma quantization deadzones.
+    pub fn luma_deadzone(self, inter: u8, intra: u8) -> Self {
+        self.set(move |p| p.analyse.i_luma_deadzone = [inter as c_int, intra as c_int])
+    }
+    /// compute PSNR stats.
+    pub fn psnr(self, enabled: bool) -> Self {
+        self.set(move |p| p.analyse.b_psnr = enabled as c_int)
+    }
+    /// compute SSIM stats.
+    pub fn ssim(self, enabled: bool) -> Self {
+        self.set(move |p| p.analyse.b_ssim = enabled as c_int)
+    }
+    /// use the `MbInfoMap` passed along with input pictures.
+    pub fn mb_info(self, enabled: bool) -> Self {
+        self.set(move |p| p.analyse.b_mb_info = enabled as c_int)
+    }
+    /// have x264 update the constant flags of each `MbInfoMap`.
+    pub fn mb_info_update(self, enabled: bool) -> Self {
+        self.set(move |p| p.analyse.b_mb_info_update = enabled as c_int)
+    }
+
+    // RATE CONTROL
+
+    pub fn rate_control(self, rc: RateControl) -> Self {
+        self.set(move |p| match rc {
+            RateControl::Cqp(qp) => {
+                p.rc.i_rc_method = raw::X264_RC_CQP as c_int;
+                p.rc.i_qp_constant = qp as c_int;
+            }
+            RateControl::Crf(rf) => {
+                p.rc.i_rc_method = raw::X264_RC_CRF as c_int;
+                p.rc.f_rf_constant = rf;
+            }
+            RateControl::Abr(bitrate) => {
+                p.rc.i_rc_method = raw::X264_RC_ABR as c_int;
+                p.rc.i_bitrate = bitrate as c_int;
+            }
+        })
+    }
+    pub fn qp_range(self, min: u8, max: u8) -> Self {
+        self.set(move |p| {
+            p.rc.i_qp_min = min as c_int;
+            p.rc.i_qp_max = max as c_int;
+        })
+    }
+    /// max QP step between frames.
+    pub fn qp_step(self, step: u8) -> Self {
+        self.set(move |p| p.rc.i_qp_step = step as c_int)
+    }
+    /// in CRF mode, maximum CRF as caused by VBV.
+    pub fn crf_max(self, rf: f32) -> Self {
+        self.set(move |p| p.rc.f_rf_constant_max = rf)
+    }
+    pub fn rate_tolerance(self, tolerance: f32) -> Self {
+        self.set(move |p| p.rc.f_rate_tolerance = tolerance)
+    }
+    /// VBV max bitrate and buffer size, both in kbit.
+    pub fn vbv(self, max_bitrate: u32, buffer_size: u32) -> Self {
+        self.set(move |p| {
+            p.rc.i_vbv_max_bitrate = max_bitrate as c_int;
+            p.rc.i_vbv_buffer_size = buffer_size as c_int;
+        })
+    }
+    /// <=1: fraction of buffer_size. >1: kbit.
+    pub fn vbv_init(self, init: f32) -> Self {
+        self.set(move |p| p.rc.f_vbv_buffer_init = init)
+    }
+    pub fn ip_factor(self, factor: f32) -> Self {
+        self.set(move |p| p.rc.f_ip_factor = factor)
+    }
+    pub fn pb_factor(self, factor: f32) -> Self {
+        self.set(move |p| p.rc.f_pb_factor = factor)
+    }
+    /// force CBR VBV and use filler bytes to ensure hard-CBR.
+    pub fn filler(self, enabled: bool) -> Self {
+        self.set(move |p| p.rc.b_filler = enabled as c_int)
+    }
+    pub fn adaptive_quant(self, mode: AdaptiveQuant, strength: f32) -> Self {
+        self.set(move |p| {
+            p.rc.i_aq_mode = mode as c_int;
+            p.rc.f_aq_strength = strength;
+        })
+    }
+    /// macroblock-tree ratecontrol.
+    pub fn mb_tree(self, enabled: bool) -> Self {
+        self.set(move |p| p.rc.b_mb_tree = enabled as c_int)
+    }
+    /// number of frames for frametype and ratecontrol lookahead.
+    pub fn rc_lookahead(self, frames: u32) -> Self {
+        self.set(move |p| p.rc.i_lookahead = frames as c_int)
+    }
+    /// 0.0 => cbr, 1.0 => constant qp.
+    pub fn qcompress(self, qcompress: f32) -> Self {
+        self.set(move |p| p.rc.f_qcompress = qcompress)
+    }
+    /// temporally blur quants and complexity.
+    pub fn qblur(self, qblur: f32, complexity_blur: f32) -> Self {
+        self.set(move |p| {
+            p.rc.f_qblur = qblur;
+            p.rc.f_complexity_blur = complexity_blur;
+        })
+    }
+
+    // MUXING
+
+    pub fn frame_packing(self, arrangement: u8) -> Self {
+        self.set(move |p| p.i_frame_packing = arrangement as c_int)
+    }
+    /// generate access unit delimiters.
+    pub fn aud(self, enabled: bool) -> Self {
+        self.set(move |p| p.b_aud = enabled as c_int)
+    }
+    /// put SPS/PPS before each keyframe.
+    pub fn repeat_headers(self, enabled: bool) -> Self {
+        self.set(move |p| p.b_repeat_headers = enabled as c_int)
+    }
+    /// place start codes before NAL units, otherwise a 4 byte size.
+    pub fn annexb(self, enabled: bool) -> Self {
+        self.set(move |p| p.b_annexb = enabled as c_int)
+    }
+    pub fn sps_id(self, id: u8) -> Self {
+        self.set(move |p| p.i_sps_id = id as c_int)
+    }
+    pub fn fps(self, num: u32, den: u32) -> Self {
+        self.set(move |p| {
+            p.i_fps_num = num;
+            p.i_fps_den = den;
+        })
+    }
+    pub fn timebase(self, num: u32, den: u32) -> Self {
+        self.set(move |p| {
+            p.i_timebase_num = num;
+            p.i_timebase_den = den;
+        })
+    }
+    /// use timebase and timestamps for ratecontrol purposes, otherwise fps only.
+    pub fn vfr_input(self, enabled: bool) -> Self {
+        self.set(move |p| p.b_vfr_input = enabled as c_int)
+    }
+    /// use explicitly set timebase for CFR.
+    pub fn pulldown(self, enabled: bool) -> Self {
+        self.set(move |p| p.b_pulldown = enabled as c_int)
+    }
+    pub fn pic_struct(self, enabled: bool) -> Self {
+        self.set(move |p| p.b_pic_struct = enabled as c_int)
+    }
+    /// don't optimize header parameters based on video content.
+    pub fn stitchable(self, enabled: bool) -> Self {
+        self.set(move |p| p.b_stitchable = enabled as c_int)
+    }
+
+    // SLICING
+
+    /// max size per slice in bytes; includes estimated NAL overhead.
+    pub fn slice_max_size(self, bytes: u32) -> Self {
+        self.set(move |p| p.i_slice_max_size = bytes as c_int)
+    }
+    /// max number of MBs per slice; overrides the slice count.
+    pub fn slice_max_mbs(self, mbs: u32) -> Self {
+        self.set(move |p| p.i_slice_max_mbs = mbs as c_int)
+    }
+    pub fn slice_min_mbs(self, mbs: u32) -> Self {
+        self.set(move |p| p.i_slice_min_mbs = mbs as c_int)
+    }
+    /// number of slices per frame: forces rectangular slices.
+    pub fn slice_count(self, count: u32) -> Self {
+        self.set(move |p| p.i_slice_count = count as c_int)
+    }
+    /// absolute cap on slices per frame.
+    pub fn slice_count_max(self, count: u32) -> Self {
+        self.set(move |p| p.i_slice_count_max = count as c_int)
+    }
+
+    // BUILD
+
+    pub fn build(self) -> Result<Params> {
+        let ParamsBuilder {preset, tune, profile, log_label, color, mut zones, setters, checks} = self;
+        let mut raw: X264ParamT = unsafe { std::mem::zeroed() };
+        let preset_name = CString::new(preset.as_str()).expect("preset name");
+        let tune_name = tune
+            .to_tune_string()
+            .map(|x| CString::new(x).expect("tune name"));
+        let status = unsafe {
+            sys::x264_param_default_preset(
+                &mut raw,
+                preset_name.as_ptr(),
+                tune_name.as_ref().map(|x| x.as_ptr()).unwrap_or(ptr::null()),
+            )
+        };
+        if status < 0 {
+            let name = tune
+                .to_tune_string()
+                .map(|x| format!("{}:{}", preset.as_str(), x))
+                .unwrap_or_else(|| preset.as_str().to_owned());
+            return Err(Error::InvalidPreset(name));
+        }
+        for setter in setters {
+            setter(&mut raw)?;
+        }
+        validate(&raw, profile)?;
+        if zones.is_empty() && !raw.rc.psz_zones.is_null() {
+            let psz_zones = unsafe { CStr::from_ptr(raw.rc.psz_zones) };
+            zones = zone::parse(&psz_zones.to_string_lossy())?;
+        }
+        validate_zones(&raw, &zones)?;
+        if let Some(profile) = profile {
+            let profile_name = CString::new(profile.as_str()).expect("profile name");
+            let status = unsafe {
+                sys::x264_param_apply_profile(&mut raw, profile_name.as_ptr())
+            };
+            if status < 0 {
+                return Err(Error::InvalidProfile(profile.as_str().to_owned()));
+            }
+        }
+        for check in checks {
+            check(&raw)?;
+        }
+        Ok(Params {raw, log_label, color, zones})
+    }
+}
+
+fn parse_option(raw: &mut X264ParamT, name: &str, value: Option<&str>) -> Result<()> {
+    let bad_value = || Error::BadParamValue {
+        name: name.to_owned(),
+        value: value.map(ToOwned::to_owned),
+    };
+    let c_name = CString::new(name).map_err(|_| Error::BadParamName(name.to_owned()))?;
+    let c_value = match value {
+        Some(value) => Some(CString::new(value).map_err(|_| bad_value())?),
+        None => None,
+    };
+    let status = unsafe {
+        sys::x264_param_parse(
+            raw,
+            c_name.as_ptr(),
+            c_value.as_ref().map(|x| x.as_ptr()).unwrap_or(ptr::null()),
+        )
+    };
+    match status {
+        0 => Ok(()),
+        raw::X264_PARAM_BAD_NAME => Err(Error::BadParamName(name.to_owned())),
+        _ => Err(bad_value()),
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// VALIDATION
+///////////////////////////////////////////////////////////////////////////////
+
+fn invalid<T>(reason: String) -> Result<T> {
+    Err(Error::InvalidParams(reason))
+}
+
+/// Checks the combinations x264 would reject (or silently adjust) at
+/// x264_param_apply_profile or x264_encoder_open time.
+fn validate(p: &X264ParamT, profile: Option<Profile>) -> Result<()> {
+    let chroma = p.i_csp as u32 & raw::X264_CSP_MASK;
+    if p.i_width <= 0 || p.i_height <= 0 {
+        return invalid(format!("invalid resolution {}x{}", p.i_width, p.i_height));
+    }
+    if chroma == raw::X264_CSP_I420 && (p.i_width % 2 != 0 || p.i_height % 2 != 0) {
+        return invalid(format!("4:2:0 requires mod 2 resolution, got {}x{}", p.i_width, p.i_height));
+    }
+    if chroma == raw::X264_CSP_I422 && p.i_width % 2 != 0 {
+        return invalid(format!("4:2:2 requires mod 2 width, got {}", p.i_width));
+    }
+    if p.i_bitdepth != 8 && p.i_bitdepth != 10 {
+        return invalid(format!("unsupported bit depth {}", p.i_bitdepth));
+    }
+    if p.i_fps_num == 0 || p.i_fps_den == 0 {
+        return invalid(format!("invalid framerate {}/{}", p.i_fps_num, p.i_fps_den));
+    }
+    let qp_max_spec = 51 + 6 * (p.i_bitdepth - 8);
+    match p.rc.i_rc_method as u32 {
+        raw::X264_RC_CQP if p.rc.i_qp_constant > qp_max_spec => {
+            return invalid(format!("qp {} is out of range 0..={}", p.rc.i_qp_constant, qp_max_spec));
+        }
+        raw::X264_RC_CRF if !(0.0..=qp_max_spec as f32).contains(&p.rc.f_rf_constant) => {
+            return invalid(format!("crf {} is out of range 0..={}", p.rc.f_rf_constant, qp_max_spec));
+        }
+        raw::X264_RC_ABR if p.rc.i_bitrate <= 0 => {
+            return invalid(String::from("ABR requires a bitrate"));
+        }
+        _ => {}
+    }
+    if p.rc.i_qp_min > p.rc.i_qp_max {
+        return invalid(format!("qp min {} exceeds qp max {}", p.rc.i_qp_min, p.rc.i_qp_max));
+    }
+    if (p.rc.i_vbv_max_bitrate > 0) != (p.rc.i_vbv_buffer_size > 0) {
+        return invalid(String::from("VBV requires both a max bitrate and a buffer size"));
+    }
+    if let Some(profile) = profile {
+        validate_profile(p, profile)?;
+    }
+    if p.i_level_idc > 0 {
+        let level = Level::from_level_idc(p.i_level_idc as u8)
+            .ok_or_else(|| Error::InvalidParams(format!("invalid level_idc {}", p.i_level_idc)))?;
+        level
+            .validate(p, profile)
+            .map_err(|violation| Error::LevelExceeded {level, violation})?;
+    }
+    Ok(())
+}
+
+fn validate_zones(p: &X264ParamT, zones: &[Zone]) -> Result<()> {
+    let frame_total = if p.i_frame_total > 0 {Some(p.i_frame_total as u32)} else {None};
+    zone::validate(zones, frame_total)?;
+    // bad options would only be reported once the encoder is opened
+    let mut scratch = *p;
+    for (name, value) in zones.iter().flat_map(|x| &x.params_override) {
+        parse_option(&mut scratch, name, value.as_deref())?;
+    }
+    Ok(())
+}
+
+fn validate_profile(p: &X264ParamT, profile: Profile) -> Result<()> {
+    let chroma = p.i_csp as u32 & raw::X264_CSP_MASK;
+    let lossless = match p.rc.i_rc_method as u32 {
+        raw::X264_RC_CQP => p.rc.i_qp_constant <= 0,
+        raw::X264_RC_CRF => p.rc.f_rf_constant + 6.0 * (p.i_bitdepth - 8) as f32 <= 0.0,
+        _ => false,
+    };
+    let name = profile.as_str();
+    if profile < Profile::High444 && lossless {
+        return invalid(format!("{} profile doesn't support lossless", name));
+    }
+    if profile < Profile::High444 && chroma >= raw::X264_CSP_I444 {
+        return invalid(format!("{} profile doesn't support 4:4:4", name));
+    }
+    if profile < Profile::High422 && chroma >= raw::X264_CSP_I422 {
+        return invalid(format!("{} profile doesn't support 4:2:2", name));
+    }
+    if profile < Profile::High10 && p.i_bitdepth > 8 {
+        return invalid(format!("{} profile doesn't support a bit depth of {}", name, p.i_bitdepth));
+    }
+    if profile < Profile::High && chroma == raw::X264_CSP_I400 {
+        return invalid(format!("{} profile doesn't support 4:0:0", name));
+    }
+    if profile == Profile::Baseline && (p.b_interlaced != 0 || p.b_fake_interlaced != 0) {
+        return invalid(String::from("baseline profile doesn't support interlacing"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder() -> ParamsBuilder {
+        Params::builder().size(64, 48).fps(25, 1)
+    }
+
+    fn rejected(builder: ParamsBuilder) -> bool {
+        matches!(builder.build(), Err(Error::InvalidParams(_)))
+    }
+
+    #[test]
+    fn valid() {
+        assert!(builder().build().is_ok());
+    }
+
+    #[test]
+    fn resolution() {
+        assert!(rejected(Params::builder().fps(25, 1)));
+        assert!(rejected(builder().size(63, 48)));
+        assert!(rejected(builder().size(64, 47)));
+        assert!(rejected(builder().chroma_format(ChromaFormat::I422).size(63, 48)));
+        assert!(builder().chroma_format(ChromaFormat::I422).size(64, 47).build().is_ok());
+        assert!(builder().chroma_format(ChromaFormat::I444).size(63, 47).build().is_ok());
+    }
+
+    #[test]
+    fn bit_depth() {
+        assert!(rejected(builder().bit_depth(9)));
+        assert!(rejected(builder().bit_depth(12)));
+    }
+
+    #[test]
+    fn fps() {
+        assert!(rejected(builder().fps(0, 1)));
+        assert!(rejected(builder().fps(25, 0)));
+    }
+
+    #[test]
+    fn rate_control() {
+        assert!(rejected(builder().rate_control(RateControl::Cqp(52))));
+        assert!(rejected(builder().rate_control(RateControl::Crf(51.5))));
+        assert!(rejected(builder().rate_control(RateControl::Crf(-1.0))));
+        assert!(rejected(builder().rate_control(RateControl::Abr(0))));
+        assert!(rejected(builder().qp_range(40, 20)));
+        assert!(builder().bit_depth(10).rate_control(RateControl::Cqp(63)).build().is_ok());
+    }
+
+    #[test]
+    fn vbv() {
+        assert!(rejected(builder().vbv(1000, 0)));
+        assert!(rejected(builder().vbv(0, 1000)));
+        assert!(builder().vbv(1000, 1000).build().is_ok());
+    }
+
+    #[test]
+    fn profile() {
+        assert!(rejected(builder().profile(Profile::High).rate_control(RateControl::Cqp(0))));
+        assert!(rejected(builder().profile(Profile::High422).chroma_format(ChromaFormat::I444)));
+        assert!(rejected(builder().profile(Profile::High10).chroma_format(ChromaFormat::I422)));
+        assert!(rejected(builder().profile(Profile::High).bit_depth(10)));
+        assert!(rejected(builder().profile(Profile::Main).chroma_format(ChromaFormat::I400)));
+        assert!(rejected(builder().profile(Profile::Baseline).interlaced(Some(true))));
+        assert!(builder().profile(Profile::High444).rate_control(RateControl::Cqp(0)).build().is_ok());
+    }
+
+    #[test]
+    fn level() {
+        assert!(rejected(builder().level_idc(7)));
+        assert!(matches!(
+            builder().size(1920, 1080).level(Level::L3).build(),
+            Err(Error::LevelExceeded {level: Level::L3, ..}),
+        ));
+        assert!(builder().size(1920, 1080).level(Level::L4).build().is_ok());
+    }
+
+    #[test]
+    fn setter_order() {
+        // after the preset
+        let params = builder().preset(Preset::Ultrafast).subpel_refine(5).build().unwrap();
+        assert_eq!(params.as_raw().analyse.i_subpel_refine, 5);
+        // in call order
+        let params = builder().bframes(2).option("bframes", Some("5")).build().unwrap();
+        assert_eq!(params.as_raw().i_bframe, 5);
+        // before the profile
+        let params = builder().profile(Profile::Baseline).bframes(3).cabac(true).build().unwrap();
+        assert_eq!(params.as_raw().i_bframe, 0);
+        assert_eq!(params.as_raw().b_cabac, 0);
+    }
+}
@@ -0,0 +1,182 @@
+use std::marker::PhantomData;
+use std::os::raw::c_int;
+use std::slice;
+
+use crate::colorspace::{Colorspace, Csp};
+use crate::error::{Error, Result};
+use crate::sys::{self, X264PictureT};
+
+///////////////////////////////////////////////////////////////////////////////
+// PICTURE
+///////////////////////////////////////////////////////////////////////////////
+
+/// An input picture.
+///
+/// Either allocated by x264 through `Picture::new`, in which case it is
+/// released with x264_picture_clean when dropped, or wrapping caller-owned
+/// plane buffers through `Picture::from_planes`, without copying.
+pub struct Picture<'buf> {
+    raw: X264PictureT,
+    csp: Csp,
+    width: u32,
+    height: u32,
+    owned: bool,
+    marker: PhantomData<&'buf mut [u8]>,
+}
+
+/// x264 keeps no reference to the plane buffers, they may be sent along with
+/// the picture.
+unsafe impl<'buf> Send for Picture<'buf> {}
+
+impl Picture<'static> {
+    /// alloc a picture of the given colorspace and dimensions.
+    ///
+    /// V210 can not be allocated by x264, use `from_planes` instead.
+    pub fn new(csp: impl Into<Csp>, width: u32, height: u32) -> Result<Self> {
+        let csp = csp.into();
+        check_dimensions(width, height)?;
+        unsafe {
+            let mut raw: X264PictureT = std::mem::zeroed();
+            let status = sys::x264_picture_alloc(
+                &mut raw,
+                csp.to_raw(),
+                width as c_int,
+                height as c_int,
+            );
+            if status < 0 {
+                return Err(Error::AllocFailed);
+            }
+            Ok(Picture {
+                raw,
+                csp,
+                width,
+                height,
+                owned: true,
+                marker: PhantomData,
+            })
+        }
+    }
+}
+
+impl<'buf> Picture<'buf> {
+    /// wrap caller-owned planes, given as (buffer, stride in bytes) pairs.
+    ///
+    /// Each buffer must hold at least `stride` bytes for every row of its
+    /// plane, and the stride must cover a full row.
+    pub fn from_planes(
+        csp: impl Into<Csp>,
+        width: u32,
+        height: u32,
+        planes: Vec<(&'buf mut [u8], usize)>,
+    ) -> Result<Self> {
+        let csp = csp.into();
+        let colorspace = csp.colorspace;
+        check_dimensions(width, height)?;
+        if planes.len() != colorspace.plane_count() {
+            return Err(Error::InvalidPicture(format!(
+                "{:?} requires {} planes, got {}",
+                colorspace,
+                colorspace.plane_count(),
+                planes.len(),
+            )));
+        }
+        let mut raw: X264PictureT = unsafe { std::mem::zeroed() };
+        unsafe { sys::x264_picture_init(&mut raw) };
+        raw.img.i_csp = csp.to_raw();
+        raw.img.i_plane = planes.len() as c_int;
+        for (i, (buffer, stride)) in planes.into_iter().enumerate() {
+            let row_bytes = csp.row_bytes(i, width);
+            let rows = csp.plane_rows(i, height);
+            if stride < row_bytes || stride > c_int::MAX as usize {
+                return Err(Error::InvalidPicture(format!(
+                    "plane {} stride {} is less than a row of {} bytes",
+                    i, stride, row_bytes,
+                )));
+            }
+            if buffer.len() < stride * rows {
+                return Err(Error::InvalidPicture(format!(
+                    "plane {} holds {} bytes, expected {}",
+                    i, buffer.len(), stride * rows,
+                )));
+            }
+            raw.img.i_stride[i] = stride as c_int;
+            raw.img.plane[i] = buffer.as_mut_ptr();
+        }
+        Ok(Picture {
+            raw,
+            csp,
+            width,
+            height,
+            owned: false,
+            marker: PhantomData,
+        })
+    }
+
+    pub fn csp(&self) -> Csp {
+        self.csp
+    }
+
+    pub fn colorspace(&self) -> Colorspace {
+        self.csp.colorspace
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn plane_count(&self) -> usize {
+        self.raw.img.i_plane as usize
+    }
+
+    /// Stride of the given plane in bytes.
+    pub fn stride(&self, plane: usize) -> usize {
+        assert!(plane < self.plane_count(), "plane index out of range");
+        self.raw.img.i_stride[plane] as usize
+    }
+
+    pub fn plane(&self, plane: usize) -> &[u8] {
+        let len = self.plane_len(plane);
+        unsafe { slice::from_raw_parts(self.raw.img.plane[plane], len) }
+    }
+
+    pub fn plane_mut(&mut self, plane: usize) -> &mut [u8] {
+        let len = self.plane_len(plane);
+        unsafe { slice::from_raw_parts_mut(self.raw.img.plane[plane], len) }
+    }
+
+    /// user pts.
+    pub fn pts(&self) -> i64 {
+        self.raw.i_pts
+    }
+
+    pub fn set_pts(&mut self, pts: i64) {
+        self.raw.i_pts = pts;
+    }
+
+    pub fn as_raw(&self) -> &X264PictureT {
+        &self.raw
+    }
+
+    fn plane_len(&self, plane: usize) -> usize {
+        self.stride(plane) * self.csp.plane_rows(plane, self.height)
+    }
+}
+
+impl<'buf> Drop for Picture<'buf> {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe { sys::x264_picture_clean(&mut self.raw) }
+        }
+    }
+}
+
+fn check_dimensions(width: u32, height: u32) -> Result<()> {
+    if width == 0 || height == 0 || width > i16::MAX as u32 || height > i16::MAX as u32 {
+        return Err(Error::InvalidPicture(format!("invalid dimensions {}x{}", width, height)));
+    }
+    Ok(())
+}
@@ -0,0 +1,253 @@
+use crate::frame::FrameType;
+use crate::sys::X264ImagePropertiesT;
+
+///////////////////////////////////////////////////////////////////////////////
+// FRAME QUALITY
+///////////////////////////////////////////////////////////////////////////////
+
+/// PSNR of each plane and of the whole picture, in dB.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Psnr {
+    pub y: f64,
+    pub u: f64,
+    pub v: f64,
+    /// over the samples of all three planes.
+    pub avg: f64,
+}
+
+/// Quality metrics of an encoded frame, as reported in `pic_out.prop`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FrameQuality {
+    /// only computed if `analyse.b_psnr` is set.
+    pub psnr: Option<Psnr>,
+    /// SSIM of the frame luma, only computed if `analyse.b_ssim` is set.
+    pub ssim: Option<f64>,
+    /// average effective CRF of the frame.
+    pub crf_avg: f64,
+}
+
+impl FrameQuality {
+    pub(crate) fn from_raw(prop: &X264ImagePropertiesT, psnr: bool, ssim: bool) -> Self {
+        FrameQuality {
+            psnr: if psnr {
+                Some(Psnr {
+                    y: prop.f_psnr[0],
+                    u: prop.f_psnr[1],
+                    v: prop.f_psnr[2],
+                    avg: prop.f_psnr_avg,
+                })
+            } else {
+                None
+            },
+            ssim: if ssim {Some(prop.f_ssim)} else {None},
+            crf_avg: prop.f_crf_avg,
+        }
+    }
+
+    /// SSIM in dB, as printed by x264.
+    pub fn ssim_db(&self) -> Option<f64> {
+        self.ssim.map(ssim_db)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// QUALITY STATS
+///////////////////////////////////////////////////////////////////////////////
+
+/// Running totals of `FrameQuality` over a stream, per frame type like the
+/// summary x264 logs when closed.
+///
+/// Frames are weighted equally, whereas x264 weights them by duration; the
+/// two only differ with a variable framerate.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct QualityStats {
+    i: Totals,
+    p: Totals,
+    b: Totals,
+}
+
+impl QualityStats {
+    pub fn new() -> Self {
+        QualityStats::default()
+    }
+
+    pub fn record(&mut self, frame_type: FrameType, quality: &FrameQuality) {
+        let totals = if frame_type.is_intra() {
+            &mut self.i
+        } else if frame_type.is_b() {
+            &mut self.b
+        } else {
+            &mut self.p
+        };
+        totals.record(quality);
+    }
+
+    /// over every frame recorded.
+    pub fn summary(&self) -> QualitySummary {
+        self.i.merge(&self.p).merge(&self.b).summary()
+    }
+
+    /// IDR and I frames.
+    pub fn i_frames(&self) -> QualitySummary {
+        self.i.summary()
+    }
+
+    pub fn p_frames(&self) -> QualitySummary {
+        self.p.summary()
+    }
+
+    /// B and B-ref frames.
+    pub fn b_frames(&self) -> QualitySummary {
+        self.b.summary()
+    }
+}
+
+/// Averages over a set of frames.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct QualitySummary {
+    pub frames: u64,
+    /// mean of the per-frame PSNRs.
+    pub psnr_mean: Option<Psnr>,
+    /// PSNR of the mean squared error over all frames, which unlike
+    /// `psnr_mean.avg` is not skewed by near-lossless frames.
+    pub psnr_global: Option<f64>,
+    /// mean of the per-frame SSIMs.
+    pub ssim: Option<f64>,
+    pub crf_avg: Option<f64>,
+}
+
+impl QualitySummary {
+    /// mean SSIM in dB, as printed by x264.
+    pub fn ssim_db(&self) -> Option<f64> {
+        self.ssim.map(ssim_db)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct Totals {
+    frames: u64,
+    psnr_frames: u64,
+    psnr: Psnr,
+    /// sum of the normalized mean squared errors over all planes.
+    mse: f64,
+    ssim_frames: u64,
+    ssim: f64,
+    crf: f64,
+}
+
+impl Totals {
+    fn record(&mut self, quality: &FrameQuality) {
+        self.frames += 1;
+        self.crf += quality.crf_avg;
+        if let Some(psnr) = quality.psnr {
+            self.psnr_frames += 1;
+            self.psnr.y += psnr.y;
+            self.psnr.u += psnr.u;
+            self.psnr.v += psnr.v;
+            self.psnr.avg += psnr.avg;
+            // x264 reports 100dB for a lossless frame, the MSE is tiny either way
+            self.mse += 10f64.powf(-psnr.avg / 10.0);
+        }
+        if let Some(ssim) = quality.ssim {
+            self.ssim_frames += 1;
+            self.ssim += ssim;
+        }
+    }
+
+    fn merge(&self, other: &Totals) -> Totals {
+        Totals {
+            frames: self.frames + other.frames,
+            psnr_frames: self.psnr_frames + other.psnr_frames,
+            psnr: Psnr {
+                y: self.psnr.y + other.psnr.y,
+                u: self.psnr.u + other.psnr.u,
+                v: self.psnr.v + other.psnr.v,
+                avg: self.psnr.avg + other.psnr.avg,
+            },
+            mse: self.mse + other.mse,
+            ssim_frames: self.ssim_frames + other.ssim_frames,
+            ssim: self.ssim + other.ssim,
+            crf: self.crf + other.crf,
+        }
+    }
+
+    fn summary(&self) -> QualitySummary {
+        let psnr_count = self.psnr_frames as f64;
+        let psnr = self.psnr_frames > 0;
+        QualitySummary {
+            frames: self.frames,
+            psnr_mean: if psnr {
+                Some(Psnr {
+                    y: self.psnr.y / psnr_count,
+                    u: self.psnr.u / psnr_count,
+                    v: self.psnr.v / psnr_count,
+                    avg: self.psnr.avg / psnr_count,
+                })
+            } else {
+                None
+            },
+            psnr_global: if psnr {Some(psnr_of_mse(self.mse / psnr_count))} else {None},
+            ssim: if self.ssim_frames > 0 {
+                Some(self.ssim / self.ssim_frames as f64)
+            } else {
+                None
+            },
+            crf_avg: if self.frames > 0 {Some(self.crf / self.frames as f64)} else {None},
+        }
+    }
+}
+
+/// Same cap as x264's calc_psnr.
+fn psnr_of_mse(mse: f64) -> f64 {
+    if mse <= 0.0000000001 {
+        return 100.0;
+    }
+    -10.0 * mse.log10()
+}
+
+/// Same cap as x264's calc_ssim_db.
+fn ssim_db(ssim: f64) -> f64 {
+    let inv_ssim = 1.0 - ssim;
+    if inv_ssim <= 0.0000000001 {
+        return 100.0;
+    }
+    -10.0 * inv_ssim.log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quality(psnr: f64, ssim: f64) -> FrameQuality {
+        FrameQuality {
+            psnr: Some(Psnr {y: psnr, u: psnr, v: psnr, avg: psnr}),
+            ssim: Some(ssim),
+            crf_avg: 23.0,
+        }
+    }
+
+    #[test]
+    fn db_conversion() {
+        assert!((psnr_of_mse(0.001) - 30.0).abs() < 1e-9);
+        assert!((psnr_of_mse(1e-6) - 60.0).abs() < 1e-9);
+        assert!((ssim_db(0.99) - 20.0).abs() < 1e-9);
+        assert_eq!(ssim_db(1.0), 100.0);
+        assert_eq!(psnr_of_mse(0.0), 100.0);
+        assert_eq!(quality(40.0, 1.0).ssim_db(), Some(100.0));
+    }
+
+    #[test]
+    fn global_psnr() {
+        let mut stats = QualityStats::new();
+        stats.record(FrameType::Idr, &quality(30.0, 0.99));
+        stats.record(FrameType::P, &quality(40.0, 0.999));
+        let summary = stats.summary();
+        assert_eq!(summary.frames, 2);
+        assert_eq!(summary.psnr_mean.unwrap().avg, 35.0);
+        // of the mean MSE, (1e-3 + 1e-4) / 2
+        assert!((summary.psnr_global.unwrap() - 32.596_373).abs() < 1e-6);
+        assert!((summary.ssim.unwrap() - 0.9945).abs() < 1e-12);
+        assert_eq!(stats.i_frames().frames, 1);
+        assert_eq!(stats.b_frames().psnr_global, None);
+    }
+}
@@ -0,0 +1,281 @@
+use std::os::raw::{c_int, c_void};
+
+use crate::encoder::Encoder;
+use crate::error::{Error, Result};
+use crate::raw;
+use crate::sys::{X264ParamT, X264PictureT};
+
+///////////////////////////////////////////////////////////////////////////////
+// REGION
+///////////////////////////////////////////////////////////////////////////////
+
+/// An area of the picture, in luma pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    Rect {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    Ellipse {
+        center_x: u32,
+        center_y: u32,
+        radius_x: u32,
+        radius_y: u32,
+    },
+}
+
+impl Region {
+    /// Distance in pixels from the point to the region, zero inside it.
+    fn distance(&self, px: f32, py: f32) -> f32 {
+        match *self {
+            Region::Rect {x, y, width, height} => {
+                let (x0, y0) = (x as f32, y as f32);
+                let (x1, y1) = (x0 + width as f32, y0 + height as f32);
+                let dx = (x0 - px).max(px - x1).max(0.0);
+                let dy = (y0 - py).max(py - y1).max(0.0);
+                dx.hypot(dy)
+            }
+            Region::Ellipse {center_x, center_y, radius_x, radius_y} => {
+                let dx = px - center_x as f32;
+                let dy = py - center_y as f32;
+                let r = (dx / radius_x.max(1) as f32).hypot(dy / radius_y.max(1) as f32);
+                if r <= 1.0 {
+                    0.0
+                } else {
+                    // along the ray from the center, past the boundary
+                    dx.hypot(dy) * (1.0 - 1.0 / r)
+                }
+            }
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// QUANT OFFSET MAP
+///////////////////////////////////////////////////////////////////////////////
+
+/// Per-macroblock quantizer offsets for a single frame (`prop.quant_offsets`).
+///
+/// Offsets are added on top of the decisions made by x264, negative values
+/// keep a region sharp and positive values starve it. They can be fractional;
+/// they are added before QPs are rounded to integer. Adaptive quantization
+/// must be enabled to use this feature.
+///
+/// Macroblocks are in raster scan order. In interlaced mode, top-field MBs and
+/// bottom-field MBs are interleaved at the row level.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantOffsetMap {
+    mb_width: u32,
+    mb_height: u32,
+    offsets: Vec<f32>,
+}
+
+impl QuantOffsetMap {
+    /// a map of zero offsets.
+    pub fn new(mb_width: u32, mb_height: u32) -> Self {
+        QuantOffsetMap {
+            mb_width,
+            mb_height,
+            offsets: vec![0.0; (mb_width * mb_height) as usize],
+        }
+    }
+
+    /// a map of zero offsets covering the macroblocks of `encoder`.
+    pub fn for_encoder<T>(encoder: &Encoder<T>) -> Self {
+        let (mb_width, mb_height) = encoder.mb_grid();
+        QuantOffsetMap::new(mb_width, mb_height)
+    }
+
+    pub fn mb_width(&self) -> u32 {
+        self.mb_width
+    }
+
+    pub fn mb_height(&self) -> u32 {
+        self.mb_height
+    }
+
+    pub fn get(&self, mb_x: u32, mb_y: u32) -> f32 {
+        self.offsets[self.index(mb_x, mb_y)]
+    }
+
+    pub fn set(&mut self, mb_x: u32, mb_y: u32, offset: f32) {
+        let index = self.index(mb_x, mb_y);
+        self.offsets[index] = offset;
+    }
+
+    pub fn as_slice(&self) -> &[f32] {
+        &self.offsets
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [f32] {
+        &mut self.offsets
+    }
+
+    /// set every macroblock to `offset`, e.g. to starve the background before
+    /// painting regions of interest.
+    pub fn fill(&mut self, offset: f32) {
+        self.offsets.iter_mut().for_each(|x| *x = offset);
+    }
+
+    /// set `offset` on every macroblock whose center lies within `region`.
+    pub fn paint(&mut self, region: Region, offset: f32) {
+        self.paint_feathered(region, offset, 0);
+    }
+
+    /// `paint`, fading linearly into the existing offsets over `feather`
+    /// pixels outside of `region`.
+    pub fn paint_feathered(&mut self, region: Region, offset: f32, feather: u32) {
+        for mb_y in 0..self.mb_height {
+            for mb_x in 0..self.mb_width {
+                let distance = region.distance(
+                    (mb_x * 16 + 8) as f32,
+                    (mb_y * 16 + 8) as f32,
+                );
+                let weight = if distance <= 0.0 {
+                    1.0
+                } else if distance < feather as f32 {
+                    1.0 - distance / feather as f32
+                } else {
+                    continue;
+                };
+                let index = self.index(mb_x, mb_y);
+                let current = self.offsets[index];
+                self.offsets[index] = current + (offset - current) * weight;
+            }
+        }
+    }
+
+    /// Whether the map can be used by an encoder with the given parameters.
+    pub(crate) fn check(&self, param: &X264ParamT) -> Result<()> {
+        if param.rc.i_aq_mode == raw::X264_AQ_NONE as c_int {
+            return Err(Error::InvalidParams(String::from(
+                "quant offsets require adaptive quantization",
+            )));
+        }
+        let (mb_width, mb_height) = mb_grid(param);
+        if (self.mb_width, self.mb_height) != (mb_width, mb_height) {
+            return Err(Error::InvalidParams(format!(
+                "quant offset map of {}x{} MBs for a {}x{} MB encoder",
+                self.mb_width, self.mb_height, mb_width, mb_height,
+            )));
+        }
+        Ok(())
+    }
+
+    /// Hand a copy of the offsets over to x264 through `pic`, released with
+    /// `quant_offsets_free` once used.
+    pub(crate) fn attach(&self, pic: &mut X264PictureT) -> Result<()> {
+        let len = self.offsets.len() * std::mem::size_of::<f32>();
+        unsafe {
+            let buffer = libc::malloc(len.max(1)) as *mut f32;
+            if buffer.is_null() {
+                return Err(Error::AllocFailed);
+            }
+            std::ptr::copy_nonoverlapping(self.offsets.as_ptr(), buffer, self.offsets.len());
+            pic.prop.quant_offsets = buffer;
+            pic.prop.quant_offsets_free = Some(free_offsets);
+        }
+        Ok(())
+    }
+
+    fn index(&self, mb_x: u32, mb_y: u32) -> usize {
+        assert!(mb_x < self.mb_width && mb_y < self.mb_height, "macroblock out of range");
+        (mb_y * self.mb_width + mb_x) as usize
+    }
+}
+
+/// The macroblock grid of the given parameters; width and height are rounded
+/// up to the nearest 16, or height to 32 in interlaced mode.
+pub(crate) fn mb_grid(param: &X264ParamT) -> (u32, u32) {
+    mb_grid_of(
+        param.i_width.max(0) as u32,
+        param.i_height.max(0) as u32,
+        param.b_interlaced != 0 || param.b_fake_interlaced != 0,
+    )
+}
+
+/// `mb_grid` for pictures of `width` by `height`.
+pub(crate) fn mb_grid_of(width: u32, height: u32, interlaced: bool) -> (u32, u32) {
+    let mb_width = width.div_ceil(16);
+    let mut mb_height = height.div_ceil(16);
+    if interlaced {
+        mb_height = (mb_height + 1) & !1;
+    }
+    (mb_width, mb_height)
+}
+
+pub(crate) unsafe extern "C" fn free_offsets(offsets: *mut c_void) {
+    libc::free(offsets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::{AdaptiveQuant, Params};
+
+    /// the macroblocks set to a non-zero offset.
+    fn painted(map: &QuantOffsetMap) -> Vec<(u32, u32)> {
+        let mut mbs = Vec::new();
+        for mb_y in 0..map.mb_height() {
+            for mb_x in 0..map.mb_width() {
+                if map.get(mb_x, mb_y) != 0.0 {
+                    mbs.push((mb_x, mb_y));
+                }
+            }
+        }
+        mbs
+    }
+
+    #[test]
+    fn paint_rect() {
+        let mut map = QuantOffsetMap::new(8, 6);
+        // MB centers are at 8 + 16n, 40 is just inside and 56 outside
+        map.paint(Region::Rect {x: 16, y: 16, width: 30, height: 14}, -4.0);
+        assert_eq!(painted(&map), vec![(1, 1), (2, 1)]);
+        assert_eq!(map.get(1, 1), -4.0);
+    }
+
+    #[test]
+    fn paint_ellipse() {
+        let mut map = QuantOffsetMap::new(8, 6);
+        let region = Region::Ellipse {center_x: 64, center_y: 48, radius_x: 40, radius_y: 16};
+        map.paint(region, -4.0);
+        assert_eq!(
+            painted(&map),
+            vec![(2, 2), (3, 2), (4, 2), (5, 2), (2, 3), (3, 3), (4, 3), (5, 3)],
+        );
+        assert_eq!(region.distance(64.0, 48.0), 0.0);
+        assert_eq!(region.distance(104.0, 48.0), 0.0);
+        assert_eq!(region.distance(144.0, 48.0), 40.0);
+        assert_eq!(region.distance(64.0, 8.0), 24.0);
+    }
+
+    #[test]
+    fn feather() {
+        let mut map = QuantOffsetMap::new(7, 1);
+        map.fill(2.0);
+        map.paint_feathered(Region::Rect {x: 0, y: 0, width: 16, height: 16}, -6.0, 64);
+        // centers 8, 24, 40, .. are 0, 8, 24, 40, 56 and 72 pixels away
+        assert_eq!(map.as_slice(), &[-6.0, -5.0, -3.0, -1.0, 1.0, 2.0, 2.0]);
+    }
+
+    #[test]
+    fn check() {
+        let params = Params::builder().size(64, 48).build().unwrap();
+        assert_eq!(QuantOffsetMap::new(4, 3).check(params.as_raw()), Ok(()));
+        assert!(QuantOffsetMap::new(4, 4).check(params.as_raw()).is_err());
+        let params = Params::builder().size(64, 48).interlaced(Some(true)).build().unwrap();
+        assert_eq!(QuantOffsetMap::new(4, 4).check(params.as_raw()), Ok(()));
+        let params = Params::builder()
+            .size(64, 48)
+            .adaptive_quant(AdaptiveQuant::None, 1.0)
+            .build()
+            .unwrap();
+        assert!(matches!(
+            QuantOffsetMap::new(4, 3).check(params.as_raw()),
+            Err(Error::InvalidParams(_)),
+        ));
+    }
+}
@@ -3,4 +3,7 @@
 #![allow(non_upper_case_globals)]
 #![allow(non_camel_case_types)]
 
-include!(concat!(env!("OUT_DIR"), "/bindings_x264.rs"));
\ No newline at end of file
+include!(concat!(env!("OUT_DIR"), "/bindings_x264.rs"));
+
+// `x264_encoder_open` under its versioned name, see build.rs.
+include!(concat!(env!("OUT_DIR"), "/encoder_open.rs"));
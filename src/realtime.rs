@@ -0,0 +1,218 @@
+use crate::encoder::{Drain, Encoder};
+use crate::error::{Error, Result};
+use crate::frame::{EncodedFrame, FrameOptions, FrameType};
+use crate::params::{Params, ParamsBuilder, Tune};
+use crate::picture::Picture;
+use crate::sys::X264ParamT;
+
+/// the largest DPB x264 supports, X264_REF_MAX.
+const MAX_DPB_SIZE: u8 = 16;
+
+///////////////////////////////////////////////////////////////////////////////
+// LOSS RECOVERY
+///////////////////////////////////////////////////////////////////////////////
+
+/// How a `RealtimeEncoder` recovers from frames lost by the client.
+///
+/// x264 supports reference invalidation only without intra refresh, and
+/// intra refresh limits the DPB to a single frame, so a stream uses one or
+/// the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LossRecovery {
+    /// spread an intra refresh over the next frames, see
+    /// `Encoder::intra_refresh`.
+    IntraRefresh,
+    /// stop referencing the lost frames, falling back to older ones kept in
+    /// a large DPB, see `Encoder::invalidate_reference`.
+    InvalidateReference,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// REALTIME ENCODER
+///////////////////////////////////////////////////////////////////////////////
+
+/// Low-latency encoding for interactive streaming, where every picture
+/// comes out as soon as it is encoded and the client reports lost frames.
+///
+/// Keyframes are only coded on request or when nothing is left to
+/// reference, the stream otherwise recovers as per `LossRecovery`.
+pub struct RealtimeEncoder<T = ()> {
+    encoder: Encoder<T>,
+    recovery: LossRecovery,
+    /// force an IDR on the next picture.
+    refresh: bool,
+}
+
+impl<T> RealtimeEncoder<T> {
+    /// Parameters for `open`, as with `--tune zerolatency`: no B-frames, no
+    /// lookahead, sliced threads, and no keyframe interval.
+    ///
+    /// `InvalidateReference` also keeps the largest DPB possible, and has
+    /// `build` reject B-frames. Options set afterwards on the builder take
+    /// precedence.
+    pub fn builder(recovery: LossRecovery) -> ParamsBuilder {
+        let builder = Params::builder()
+            .tune(Tune::default().with_zero_latency())
+            .bframes(0)
+            .rc_lookahead(0)
+            .sync_lookahead(Some(0))
+            .sliced_threads(true)
+            .keyint_max(None);
+        match recovery {
+            LossRecovery::IntraRefresh => builder.intra_refresh(true),
+            LossRecovery::InvalidateReference => builder
+                .intra_refresh(false)
+                .dpb_size(MAX_DPB_SIZE)
+                .check(check_invalidation),
+        }
+    }
+
+    /// create a new encoder, recovering with intra refresh if `params`
+    /// enable it and reference invalidation otherwise.
+    ///
+    /// Reference invalidation requires B-frames to be disabled.
+    pub fn open(params: &Params) -> Result<Self> {
+        let raw = params.as_raw();
+        let recovery = if raw.b_intra_refresh != 0 {
+            LossRecovery::IntraRefresh
+        } else {
+            LossRecovery::InvalidateReference
+        };
+        if recovery == LossRecovery::InvalidateReference {
+            check_invalidation(raw)?;
+        }
+        let encoder = Encoder::open(params)?;
+        Ok(RealtimeEncoder {encoder, recovery, refresh: false})
+    }
+
+    pub fn recovery(&self) -> LossRecovery {
+        self.recovery
+    }
+
+    /// see `Encoder::encode`.
+    pub fn encode(&mut self, pic: &Picture, user: T) -> Result<Option<EncodedFrame<'_, T>>> {
+        self.encode_with(pic, FrameOptions::default(), user)
+    }
+
+    /// see `Encoder::encode_with`, a type forced by `options` takes
+    /// precedence over a pending `request_refresh`.
+    pub fn encode_with(
+        &mut self,
+        pic: &Picture,
+        mut options: FrameOptions,
+        user: T,
+    ) -> Result<Option<EncodedFrame<'_, T>>> {
+        if self.refresh && options.frame_type.is_none() {
+            options.frame_type = Some(FrameType::Idr);
+        }
+        let refresh = options.frame_type.is_some_and(FrameType::is_intra);
+        let frame = self.encoder.encode_with(pic, options, user)?;
+        if refresh {
+            self.refresh = false;
+        }
+        Ok(frame)
+    }
+
+    /// the client lost the frame with this pts, and possibly later ones.
+    ///
+    /// with `InvalidateReference`, the frames from `pts` onwards are no
+    /// longer referenced; with `IntraRefresh`, this is `request_refresh`.
+    pub fn report_loss(&mut self, pts: i64) -> Result<()> {
+        match self.recovery {
+            LossRecovery::IntraRefresh => {
+                self.request_refresh();
+                Ok(())
+            }
+            LossRecovery::InvalidateReference => self.encoder.invalidate_reference(pts),
+        }
+    }
+
+    /// let the client start decoding anew, e.g. once it joins the stream.
+    ///
+    /// with `IntraRefresh`, a refresh starts with the next P-frame; with
+    /// `InvalidateReference`, the next picture is coded as an IDR.
+    pub fn request_refresh(&mut self) {
+        match self.recovery {
+            LossRecovery::IntraRefresh => self.encoder.intra_refresh(),
+            LossRecovery::InvalidateReference => self.refresh = true,
+        }
+    }
+
+    pub fn encoder(&self) -> &Encoder<T> {
+        &self.encoder
+    }
+
+    pub fn encoder_mut(&mut self) -> &mut Encoder<T> {
+        &mut self.encoder
+    }
+
+    /// see `Encoder::finish`, there is nothing delayed unless the
+    /// parameters were changed from `builder`.
+    pub fn finish(self) -> Drain<T> {
+        self.encoder.finish()
+    }
+}
+
+fn check_invalidation(param: &X264ParamT) -> Result<()> {
+    if param.i_bframe != 0 {
+        return Err(Error::InvalidParams(String::from(
+            "reference invalidation is not supported with B-frames",
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colorspace::Colorspace;
+
+    fn open(recovery: LossRecovery) -> RealtimeEncoder {
+        let params = RealtimeEncoder::<()>::builder(recovery).size(64, 48).build().unwrap();
+        RealtimeEncoder::open(&params).unwrap()
+    }
+
+    #[test]
+    fn bframes() {
+        let builder = || RealtimeEncoder::<()>::builder(LossRecovery::InvalidateReference);
+        assert!(matches!(
+            builder().size(64, 48).bframes(2).build(),
+            Err(Error::InvalidParams(_)),
+        ));
+        assert!(builder().size(64, 48).build().is_ok());
+        let params = RealtimeEncoder::<()>::builder(LossRecovery::IntraRefresh)
+            .size(64, 48)
+            .bframes(2)
+            .build()
+            .unwrap();
+        let encoder = RealtimeEncoder::<()>::open(&params).unwrap();
+        assert_eq!(encoder.recovery(), LossRecovery::IntraRefresh);
+    }
+
+    #[test]
+    fn recovery() {
+        assert_eq!(open(LossRecovery::IntraRefresh).recovery(), LossRecovery::IntraRefresh);
+        assert_eq!(
+            open(LossRecovery::InvalidateReference).recovery(),
+            LossRecovery::InvalidateReference,
+        );
+    }
+
+    #[test]
+    fn request_refresh() {
+        let mut encoder = open(LossRecovery::InvalidateReference);
+        let mut pic = Picture::new(Colorspace::I420, 64, 48).unwrap();
+        let mut encode = |encoder: &mut RealtimeEncoder, options: FrameOptions| {
+            pic.set_pts(pic.pts() + 1);
+            encoder.encode_with(&pic, options, ()).unwrap().unwrap().frame_type
+        };
+        assert_eq!(encode(&mut encoder, FrameOptions::default()), FrameType::Idr);
+        assert_eq!(encode(&mut encoder, FrameOptions::default()), FrameType::P);
+        encoder.request_refresh();
+        // a forced type takes precedence, the refresh is still pending
+        let forced = FrameOptions::default().with_frame_type(FrameType::P);
+        assert_eq!(encode(&mut encoder, forced), FrameType::P);
+        assert_eq!(encode(&mut encoder, FrameOptions::default()), FrameType::Idr);
+        assert_eq!(encode(&mut encoder, FrameOptions::default()), FrameType::P);
+    }
+}
@@ -0,0 +1,306 @@
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+
+use crate::error::{Error, Result};
+use crate::sys::{X264PictureT, X264SeiPayloadT};
+
+///////////////////////////////////////////////////////////////////////////////
+// SEI MESSAGE
+///////////////////////////////////////////////////////////////////////////////
+
+/// An arbitrary user SEI payload (e.g subtitles, AFDs), written along with
+/// the frame it is passed with.
+///
+/// Payload types and syntax can be found in Annex D of the H.264
+/// Specification. `data` is the bare sei_payload, without the payload type
+/// and size header, start code or emulation prevention bytes, which x264
+/// adds itself. SEI payload alignment bits as described in Annex D must be
+/// included at the end of the payload if needed, see `from_bits`.
+///
+/// Payloads are written first in order of input, apart from in the case
+/// when HRD is enabled where payloads are written after the Buffering
+/// Period SEI.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SeiMessage {
+    pub payload_type: u32,
+    pub data: Vec<u8>,
+}
+
+impl SeiMessage {
+    /// user_data_registered_itu_t_t35 payload type.
+    pub const ITU_T_T35: u32 = 4;
+    /// user_data_unregistered payload type.
+    pub const UNREGISTERED: u32 = 5;
+
+    pub fn new(payload_type: u32, data: impl Into<Vec<u8>>) -> Result<Self> {
+        let message = SeiMessage {payload_type, data: data.into()};
+        message.check()?;
+        Ok(message)
+    }
+
+    /// a payload whose syntax ends on `bit_len` bits rather than a byte
+    /// boundary, stored most significant bit first in `data`.
+    ///
+    /// The payload alignment bits of Annex D are appended when `bit_len` is
+    /// not a multiple of 8: a one followed by zeros up to the next byte
+    /// boundary.
+    pub fn from_bits(payload_type: u32, mut data: Vec<u8>, bit_len: usize) -> Result<Self> {
+        if data.len() * 8 < bit_len {
+            return Err(Error::InvalidSei(format!(
+                "{} bits of payload in {} bytes",
+                bit_len,
+                data.len(),
+            )));
+        }
+        data.truncate(bit_len.div_ceil(8));
+        let used = bit_len % 8;
+        if used != 0 {
+            let last = data.last_mut().unwrap();
+            *last &= 0xff << (8 - used);
+            *last |= 0x80 >> used;
+        }
+        SeiMessage::new(payload_type, data)
+    }
+
+    /// Whether x264 can write the payload as is.
+    pub(crate) fn check(&self) -> Result<()> {
+        if self.data.is_empty() {
+            return Err(Error::InvalidSei(format!(
+                "empty payload of type {}",
+                self.payload_type,
+            )));
+        }
+        if self.payload_type > c_int::MAX as u32 || self.data.len() > c_int::MAX as usize {
+            return Err(Error::InvalidSei(String::from("payload type or size out of range")));
+        }
+        // a start code followed by the header of an SEI NAL unit
+        if self.data.starts_with(&[0, 0, 1, 6]) || self.data.starts_with(&[0, 0, 0, 1, 6]) {
+            return Err(Error::InvalidSei(String::from(
+                "payload must not be NAL-encapsulated",
+            )));
+        }
+        Ok(())
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// BUILDERS
+///////////////////////////////////////////////////////////////////////////////
+
+impl SeiMessage {
+    /// user_data_unregistered: a 16 byte UUID identifying the format of
+    /// `data`, as x264 does for its own version string.
+    pub fn unregistered(uuid: [u8; 16], data: &[u8]) -> Self {
+        let mut payload = Vec::with_capacity(16 + data.len());
+        payload.extend_from_slice(&uuid);
+        payload.extend_from_slice(data);
+        SeiMessage {payload_type: SeiMessage::UNREGISTERED, data: payload}
+    }
+
+    /// user_data_registered_itu_t_t35, `data` following the country and
+    /// provider codes.
+    ///
+    /// The country code is written as 0xff followed by the extension byte if
+    /// `country_code_extension` is given.
+    pub fn itu_t_t35(
+        country_code: u8,
+        country_code_extension: Option<u8>,
+        provider_code: u16,
+        data: &[u8],
+    ) -> Result<Self> {
+        let mut payload = Vec::with_capacity(4 + data.len());
+        match country_code_extension {
+            Some(extension) => payload.extend_from_slice(&[0xff, extension]),
+            None if country_code == 0xff => {
+                return Err(Error::InvalidSei(String::from(
+                    "country code 0xff requires an extension byte",
+                )));
+            }
+            None => payload.push(country_code),
+        }
+        payload.extend_from_slice(&provider_code.to_be_bytes());
+        payload.extend_from_slice(data);
+        SeiMessage::new(SeiMessage::ITU_T_T35, payload)
+    }
+
+    /// CEA-608/708 caption data in the ATSC A/53 wrapper (`GA94` user data
+    /// of type 3), with `process_cc_data_flag` set.
+    ///
+    /// Captions belong to the frame they are displayed with, so the message
+    /// is passed along with the input picture of that frame; decoders undo
+    /// the reordering of B-frames.
+    pub fn captions(packets: &[CcPacket]) -> Result<Self> {
+        if packets.len() > 31 {
+            return Err(Error::InvalidSei(format!(
+                "{} caption packets, cc_count is at most 31",
+                packets.len(),
+            )));
+        }
+        let mut data = Vec::with_capacity(7 + packets.len() * 3);
+        data.extend_from_slice(b"GA94");
+        data.push(0x03);
+        // reserved, process_cc_data_flag, additional_data_flag, cc_count
+        data.push(0xc0 | packets.len() as u8);
+        // em_data
+        data.push(0xff);
+        for packet in packets {
+            data.extend_from_slice(&packet.to_bytes());
+        }
+        // marker_bits
+        data.push(0xff);
+        SeiMessage::itu_t_t35(0xb5, None, 0x0031, &data)
+    }
+}
+
+/// The kind of a caption packet (`cc_type`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CcType {
+    /// CEA-608 field 1.
+    Ntsc608Field1 = 0,
+    /// CEA-608 field 2.
+    Ntsc608Field2 = 1,
+    /// CEA-708 DTVCC channel packet data.
+    DtvccData = 2,
+    /// CEA-708 DTVCC channel packet start.
+    DtvccStart = 3,
+}
+
+/// A construct of `cc_data`, i.e. two bytes of caption data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CcPacket {
+    pub valid: bool,
+    pub cc_type: CcType,
+    pub data: [u8; 2],
+}
+
+impl CcPacket {
+    pub fn new(cc_type: CcType, data: [u8; 2]) -> Self {
+        CcPacket {valid: true, cc_type, data}
+    }
+
+    /// A packet of no data, e.g. to keep a constant `cc_count`.
+    pub fn padding(cc_type: CcType) -> Self {
+        let data = match cc_type {
+            CcType::Ntsc608Field1 | CcType::Ntsc608Field2 => [0x80, 0x80],
+            CcType::DtvccData | CcType::DtvccStart => [0x00, 0x00],
+        };
+        CcPacket {valid: false, cc_type, data}
+    }
+
+    fn to_bytes(self) -> [u8; 3] {
+        // marker_bits, cc_valid, cc_type
+        let header = 0xf8 | (self.valid as u8) << 2 | self.cc_type as u8;
+        [header, self.data[0], self.data[1]]
+    }
+}
+
+/// Hand copies of the payloads over to x264 through `pic.extra_sei`,
+/// released with `sei_free` once written or along with the frame.
+pub(crate) fn attach(messages: &[SeiMessage], pic: &mut X264PictureT) -> Result<()> {
+    if messages.is_empty() {
+        return Ok(());
+    }
+    unsafe {
+        let payloads = libc::calloc(messages.len(), std::mem::size_of::<X264SeiPayloadT>())
+            as *mut X264SeiPayloadT;
+        if payloads.is_null() {
+            return Err(Error::AllocFailed);
+        }
+        for (i, message) in messages.iter().enumerate() {
+            let payload = libc::malloc(message.data.len()) as *mut u8;
+            if payload.is_null() {
+                for j in 0..i {
+                    libc::free((*payloads.add(j)).payload as *mut c_void);
+                }
+                libc::free(payloads as *mut c_void);
+                return Err(Error::AllocFailed);
+            }
+            ptr::copy_nonoverlapping(message.data.as_ptr(), payload, message.data.len());
+            *payloads.add(i) = X264SeiPayloadT {
+                payload_size: message.data.len() as c_int,
+                payload_type: message.payload_type as c_int,
+                payload,
+            };
+        }
+        pic.extra_sei.num_payloads = messages.len() as c_int;
+        pic.extra_sei.payloads = payloads;
+        pic.extra_sei.sei_free = Some(free_sei);
+    }
+    Ok(())
+}
+
+/// called on each payload, then on the payload array.
+unsafe extern "C" fn free_sei(buffer: *mut c_void) {
+    libc::free(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unregistered() {
+        let uuid = [0xdc, 0x45, 0xe9, 0xbd, 0xe6, 0xd9, 0x48, 0xb7,
+                    0x96, 0x2c, 0xd8, 0x20, 0xd9, 0x23, 0xee, 0xef];
+        let message = SeiMessage::unregistered(uuid, b"x264");
+        assert_eq!(message.payload_type, 5);
+        assert_eq!(&message.data[..16], &uuid);
+        assert_eq!(&message.data[16..], b"x264");
+    }
+
+    #[test]
+    fn itu_t_t35() {
+        let message = SeiMessage::itu_t_t35(0xb5, None, 0x003c, &[0x00, 0x01]).unwrap();
+        assert_eq!(message.payload_type, 4);
+        assert_eq!(message.data, [0xb5, 0x00, 0x3c, 0x00, 0x01]);
+
+        let message = SeiMessage::itu_t_t35(0xff, Some(0x01), 0x1234, &[0x42]).unwrap();
+        assert_eq!(message.data, [0xff, 0x01, 0x12, 0x34, 0x42]);
+
+        assert!(SeiMessage::itu_t_t35(0xff, None, 0x1234, &[]).is_err());
+    }
+
+    #[test]
+    fn cea608_captions() {
+        // resume caption loading, with odd parity, on field 1
+        let packets = [CcPacket::new(CcType::Ntsc608Field1, [0x94, 0x20])];
+        let message = SeiMessage::captions(&packets).unwrap();
+        assert_eq!(message.payload_type, 4);
+        assert_eq!(
+            message.data,
+            [0xb5, 0x00, 0x31, 0x47, 0x41, 0x39, 0x34, 0x03,
+             0xc1, 0xff, 0xfc, 0x94, 0x20, 0xff],
+        );
+    }
+
+    #[test]
+    fn cea708_captions() {
+        let packets = [
+            CcPacket::new(CcType::Ntsc608Field1, [0x80, 0x80]),
+            CcPacket::padding(CcType::Ntsc608Field2),
+            CcPacket::new(CcType::DtvccStart, [0x02, 0x21]),
+            CcPacket::new(CcType::DtvccData, [0x8c, 0xfe]),
+        ];
+        let message = SeiMessage::captions(&packets).unwrap();
+        assert_eq!(
+            message.data,
+            [0xb5, 0x00, 0x31, 0x47, 0x41, 0x39, 0x34, 0x03,
+             0xc4, 0xff,
+             0xfc, 0x80, 0x80,
+             0xf9, 0x80, 0x80,
+             0xff, 0x02, 0x21,
+             0xfe, 0x8c, 0xfe,
+             0xff],
+        );
+        assert!(SeiMessage::captions(&[CcPacket::padding(CcType::DtvccData); 32]).is_err());
+    }
+
+    #[test]
+    fn alignment() {
+        assert_eq!(SeiMessage::from_bits(1, vec![0xff], 3).unwrap().data, [0xf0]);
+        assert_eq!(SeiMessage::from_bits(1, vec![0xff, 0xff], 8).unwrap().data, [0xff]);
+        assert_eq!(SeiMessage::from_bits(1, vec![0x12, 0x34], 16).unwrap().data, [0x12, 0x34]);
+        assert!(SeiMessage::new(5, vec![0, 0, 1, 6]).is_err());
+        assert!(SeiMessage::new(144, vec![0, 0, 0, 1]).is_ok());
+    }
+}
@@ -0,0 +1,557 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+use crate::frame::FrameType;
+use crate::params::DirectPrediction;
+
+///////////////////////////////////////////////////////////////////////////////
+// STATS FILE
+///////////////////////////////////////////////////////////////////////////////
+
+/// The text stats file written by a pass with `rc.b_stat_write`, see
+/// `TwoPassEncoder::stats_path`.
+///
+/// Frames are in coded order, as written. Parsing then formatting a file
+/// gives back the same text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatsFile {
+    pub header: StatsHeader,
+    pub frames: Vec<FrameStats>,
+}
+
+impl StatsFile {
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        fs::read_to_string(path)
+            .map_err(|e| Error::io(path, e))?
+            .parse()
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        fs::write(path, self.to_string())
+            .map_err(|e| Error::io(path, e))
+    }
+
+    /// The frames in display order.
+    pub fn frames_in_display_order(&self) -> Vec<&FrameStats> {
+        let mut frames = self.frames.iter().collect::<Vec<_>>();
+        frames.sort_by_key(|x| x.input);
+        frames
+    }
+
+    /// The scenes of the stream, each starting at a keyframe, in display
+    /// order.
+    pub fn scenes(&self) -> Vec<Scene> {
+        let qp_offset = self.header.qp_offset();
+        let mut scenes: Vec<Scene> = Vec::new();
+        for frame in self.frames_in_display_order() {
+            let complexity = frame.complexity(qp_offset);
+            match scenes.last_mut() {
+                Some(scene) if !frame.frame_type.is_intra() => {
+                    scene.frames.end = frame.input + 1;
+                    scene.complexity += complexity;
+                    scene.bits += frame.bits();
+                }
+                _ => scenes.push(Scene {
+                    frames: frame.input..frame.input + 1,
+                    complexity,
+                    bits: frame.bits(),
+                }),
+            }
+        }
+        scenes
+    }
+
+    /// Size of the stream in bits had every frame been encoded at `qp`,
+    /// predicted from the bits and quantizer of each frame the way x264's
+    /// ratecontrol does.
+    ///
+    /// `qp` is on the scale of the frames' QPs, which x264 offsets by
+    /// `StatsHeader::qp_offset` at high bit depth. The I/P and P/B quantizer
+    /// ratios are not taken into account.
+    pub fn predicted_bits(&self, qp: f32) -> f64 {
+        let qp_offset = self.header.qp_offset();
+        self.frames.iter().map(|x| x.predicted_bits(qp, qp_offset)).sum()
+    }
+
+    /// `predicted_bits` in kbit/sec, at the framerate of the header.
+    pub fn predicted_bitrate(&self, qp: f32) -> Option<f64> {
+        let (fps_num, fps_den) = self.header.fps()?;
+        if self.frames.is_empty() || fps_num == 0 {
+            return None;
+        }
+        let duration = self.frames.len() as f64 * f64::from(fps_den) / f64::from(fps_num);
+        Some(self.predicted_bits(qp) / duration / 1000.0)
+    }
+
+    /// The lowest integer QP whose `predicted_bitrate` fits `bitrate`, on the
+    /// same scale as `predicted_bits`.
+    pub fn qp_for_bitrate(&self, bitrate: f64) -> Option<u8> {
+        let max = 51 + self.header.qp_offset() as u8;
+        (0..=max).find(|qp| self.predicted_bitrate(f32::from(*qp)).is_some_and(|x| x <= bitrate))
+    }
+}
+
+impl fmt::Display for StatsFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.header)?;
+        for frame in &self.frames {
+            writeln!(f, "{}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for StatsFile {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut lines = s.lines().enumerate();
+        let header = lines
+            .next()
+            .ok_or_else(|| parse_error("missing header"))
+            .and_then(|(_, x)| x.parse())
+            .map_err(|e| at_line(e, 0))?;
+        let frames = lines
+            .filter(|(_, x)| !x.trim().is_empty())
+            .map(|(i, x)| x.parse().map_err(|e| at_line(e, i)))
+            .collect::<Result<_>>()?;
+        Ok(StatsFile {header, frames})
+    }
+}
+
+/// Frames between two keyframes, in display order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scene {
+    pub frames: std::ops::Range<u32>,
+    /// sum of `FrameStats::complexity`.
+    pub complexity: f64,
+    pub bits: u64,
+}
+
+impl Scene {
+    pub fn average_complexity(&self) -> f64 {
+        self.complexity / self.frames.len().max(1) as f64
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// HEADER
+///////////////////////////////////////////////////////////////////////////////
+
+/// The `#options:` line: the resolution followed by the encoder's options,
+/// as x264_param2string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatsHeader {
+    pub width: u32,
+    pub height: u32,
+    /// `name=value` pairs, `None` for the rare bare flags.
+    pub options: Vec<(String, Option<String>)>,
+}
+
+impl StatsHeader {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .find(|(x, _)| x == name)
+            .and_then(|(_, value)| value.as_deref())
+    }
+
+    /// framerate as (numerator, denominator).
+    pub fn fps(&self) -> Option<(u32, u32)> {
+        let (num, den) = self.get("fps")?.split_once('/')?;
+        Some((num.parse().ok()?, den.parse().ok()?))
+    }
+
+    pub fn bit_depth(&self) -> Option<u32> {
+        self.get("bitdepth")?.parse().ok()
+    }
+
+    /// `QP_BD_OFFSET`, by which the QPs of high bit depth streams are offset.
+    pub fn qp_offset(&self) -> f32 {
+        6.0 * self.bit_depth().unwrap_or(8).saturating_sub(8) as f32
+    }
+}
+
+impl fmt::Display for StatsHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#options: {}x{}", self.width, self.height)?;
+        for (name, value) in &self.options {
+            match value {
+                Some(value) => write!(f, " {}={}", name, value)?,
+                None => write!(f, " {}", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for StatsHeader {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut tokens = s
+            .strip_prefix("#options:")
+            .ok_or_else(|| parse_error("missing #options: header"))?
+            .split_whitespace();
+        let (width, height) = tokens
+            .next()
+            .and_then(|x| x.split_once('x'))
+            .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+            .ok_or_else(|| parse_error("missing resolution"))?;
+        let options = tokens
+            .map(|token| match token.split_once('=') {
+                Some((name, value)) => (name.to_owned(), Some(value.to_owned())),
+                None => (token.to_owned(), None),
+            })
+            .collect();
+        Ok(StatsHeader {width, height, options})
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// FRAME STATS
+///////////////////////////////////////////////////////////////////////////////
+
+/// One frame record of a stats file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameStats {
+    /// frame number in display order.
+    pub input: u32,
+    /// frame number in coded order.
+    pub output: u32,
+    /// one of `Idr`, `I`, `P`, `BRef` or `B`.
+    pub frame_type: FrameType,
+    /// duration and CPB duration, in timebase units.
+    pub duration: i64,
+    pub cpb_duration: i64,
+    /// average QP of ratecontrol, and after adaptive quantization.
+    pub qp: f32,
+    pub qp_aq: f32,
+    /// bits spent on residual, motion vectors, and everything else.
+    pub tex_bits: u32,
+    pub mv_bits: u32,
+    pub misc_bits: u32,
+    /// macroblocks coded as intra, inter and skip.
+    pub intra_mbs: u32,
+    pub inter_mbs: u32,
+    pub skip_mbs: u32,
+    /// direct prediction favoured by the frame with `direct=auto`.
+    pub direct: Option<DirectPrediction>,
+    /// macroblocks referencing each frame of list 0.
+    pub ref_counts: Vec<u32>,
+    pub weights: Option<WeightStats>,
+}
+
+/// Explicit weighted prediction of the first reference, as (denominator,
+/// scale, offset).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WeightStats {
+    pub luma: (i32, i32, i32),
+    /// the denominator shared by both planes, and their (scale, offset).
+    pub chroma: Option<(i32, [(i32, i32); 2])>,
+}
+
+impl FrameStats {
+    pub fn bits(&self) -> u64 {
+        u64::from(self.tex_bits) + u64::from(self.mv_bits) + u64::from(self.misc_bits)
+    }
+
+    /// bits times quantizer scale of the frame, which ratecontrol takes as
+    /// its complexity.
+    pub fn complexity(&self, qp_offset: f32) -> f64 {
+        (f64::from(self.tex_bits) + f64::from(self.mv_bits)) * qp2qscale(self.qp, qp_offset)
+    }
+
+    /// predicted size in bits at `qp`, as x264's qscale2bits.
+    ///
+    /// `qp` is on the same scale as `self.qp`, both offset by `qp_offset`.
+    pub fn predicted_bits(&self, qp: f32, qp_offset: f32) -> f64 {
+        let old = qp2qscale(self.qp, qp_offset);
+        let new = qp2qscale(qp, qp_offset).max(0.1);
+        (f64::from(self.tex_bits) + 0.1) * (old / new).powf(1.1)
+            + f64::from(self.mv_bits) * (old.max(1.0) / new.max(1.0)).powf(0.5)
+            + f64::from(self.misc_bits)
+    }
+}
+
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let frame_type = match self.frame_type {
+            FrameType::Idr | FrameType::Keyframe => 'I',
+            FrameType::I => 'i',
+            FrameType::P => 'P',
+            FrameType::BRef => 'B',
+            FrameType::B => 'b',
+        };
+        let direct = match self.direct {
+            Some(DirectPrediction::Spatial) => 's',
+            Some(DirectPrediction::Temporal) => 't',
+            _ => '-',
+        };
+        write!(
+            f,
+            "in:{} out:{} type:{} dur:{} cpbdur:{} q:{:.2} aq:{:.2} tex:{} mv:{} misc:{} imb:{} pmb:{} smb:{} d:{} ref:",
+            self.input, self.output, frame_type, self.duration, self.cpb_duration, self.qp,
+            self.qp_aq, self.tex_bits, self.mv_bits, self.misc_bits, self.intra_mbs,
+            self.inter_mbs, self.skip_mbs, direct,
+        )?;
+        for count in &self.ref_counts {
+            write!(f, "{} ", count)?;
+        }
+        if let Some(weights) = self.weights {
+            let (denom, scale, offset) = weights.luma;
+            write!(f, "w:{},{},{}", denom, scale, offset)?;
+            match weights.chroma {
+                Some((denom, [(u_scale, u_offset), (v_scale, v_offset)])) => write!(
+                    f,
+                    ",{},{},{},{},{} ",
+                    denom, u_scale, u_offset, v_scale, v_offset,
+                )?,
+                None => write!(f, " ")?,
+            }
+        }
+        write!(f, ";")
+    }
+}
+
+impl FromStr for FrameStats {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let line = s.trim().strip_suffix(';').ok_or_else(|| parse_error("missing ';'"))?;
+        let (fields, rest) = line.split_once("ref:").ok_or_else(|| parse_error("missing ref:"))?;
+        let mut fields = fields.split_whitespace().map(|x| x.split_once(':'));
+        let mut field = |name: &str| -> Result<(&str, String)> {
+            match fields.next() {
+                Some(Some((key, value))) if key == name => Ok((value, format!("{}:", name))),
+                _ => Err(parse_error(&format!("expected {}:", name))),
+            }
+        };
+        fn number<T: FromStr>((value, name): (&str, String)) -> Result<T> {
+            value.parse().map_err(|_| parse_error(&format!("invalid {}{}", name, value)))
+        }
+        let input = number(field("in")?)?;
+        let output = number(field("out")?)?;
+        let frame_type = match field("type")?.0 {
+            "I" => FrameType::Idr,
+            "i" => FrameType::I,
+            "P" => FrameType::P,
+            "B" => FrameType::BRef,
+            "b" => FrameType::B,
+            x => return Err(parse_error(&format!("invalid type:{}", x))),
+        };
+        let duration = number(field("dur")?)?;
+        let cpb_duration = number(field("cpbdur")?)?;
+        let qp = number(field("q")?)?;
+        let qp_aq = number(field("aq")?)?;
+        let tex_bits = number(field("tex")?)?;
+        let mv_bits = number(field("mv")?)?;
+        let misc_bits = number(field("misc")?)?;
+        let intra_mbs = number(field("imb")?)?;
+        let inter_mbs = number(field("pmb")?)?;
+        let skip_mbs = number(field("smb")?)?;
+        let direct = match field("d")?.0 {
+            "s" => Some(DirectPrediction::Spatial),
+            "t" => Some(DirectPrediction::Temporal),
+            "-" => None,
+            x => return Err(parse_error(&format!("invalid d:{}", x))),
+        };
+        let (refs, weights) = match rest.split_once("w:") {
+            Some((refs, weights)) => (refs, Some(weights.trim())),
+            None => (rest, None),
+        };
+        let ref_counts = refs
+            .split_whitespace()
+            .map(|x| number((x, String::from("ref:"))))
+            .collect::<Result<_>>()?;
+        let weights = match weights {
+            Some(weights) => {
+                let values = weights
+                    .split(',')
+                    .map(|x| number::<i32>((x, String::from("w:"))))
+                    .collect::<Result<Vec<_>>>()?;
+                match values[..] {
+                    [denom, scale, offset] => Some(WeightStats {luma: (denom, scale, offset), chroma: None}),
+                    [denom, scale, offset, chroma_denom, u_scale, u_offset, v_scale, v_offset] => {
+                        Some(WeightStats {
+                            luma: (denom, scale, offset),
+                            chroma: Some((chroma_denom, [(u_scale, u_offset), (v_scale, v_offset)])),
+                        })
+                    }
+                    _ => return Err(parse_error(&format!("invalid w:{}", weights))),
+                }
+            }
+            None => None,
+        };
+        Ok(FrameStats {
+            input,
+            output,
+            frame_type,
+            duration,
+            cpb_duration,
+            qp,
+            qp_aq,
+            tex_bits,
+            mv_bits,
+            misc_bits,
+            intra_mbs,
+            inter_mbs,
+            skip_mbs,
+            direct,
+            ref_counts,
+            weights,
+        })
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// MBTREE
+///////////////////////////////////////////////////////////////////////////////
+
+/// The macroblock-tree QP offsets of one reference frame, as stored in the
+/// `.mbtree` file next to the stats file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MbTreeFrame {
+    /// the slice type x264 coded the frame as.
+    pub slice_type: u8,
+    /// QP offset of each macroblock in raster scan order.
+    pub qp_offsets: Vec<f32>,
+}
+
+impl MbTreeFrame {
+    /// Parses a whole mbtree file of frames of `mb_count` macroblocks, see
+    /// `StatsHeader` for the resolution.
+    pub fn parse_all(data: &[u8], mb_count: usize) -> Result<Vec<MbTreeFrame>> {
+        let record = 1 + mb_count * 2;
+        if mb_count == 0 || data.len() % record != 0 {
+            return Err(parse_error("mbtree size does not match the macroblock count"));
+        }
+        Ok(data
+            .chunks(record)
+            .map(|chunk| MbTreeFrame {
+                slice_type: chunk[0],
+                // fix8, big endian
+                qp_offsets: chunk[1..]
+                    .chunks(2)
+                    .map(|x| f32::from(i16::from_be_bytes([x[0], x[1]])) / 256.0)
+                    .collect(),
+            })
+            .collect())
+    }
+
+    pub fn read_all(path: impl AsRef<Path>, mb_count: usize) -> Result<Vec<MbTreeFrame>> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|e| Error::io(path, e))?;
+        MbTreeFrame::parse_all(&data, mb_count)
+    }
+
+    /// Writes the frame back as x264 does, the inverse of `parse_all`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(1 + self.qp_offsets.len() * 2);
+        data.push(self.slice_type);
+        for offset in &self.qp_offsets {
+            data.extend_from_slice(&((offset * 256.0) as i16).to_be_bytes());
+        }
+        data
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// INTERNAL HELPERS
+///////////////////////////////////////////////////////////////////////////////
+
+/// x264's qp2qscale, for QPs offset by `qp_offset` (`QP_BD_OFFSET`).
+fn qp2qscale(qp: f32, qp_offset: f32) -> f64 {
+    0.85 * 2f64.powf((f64::from(qp) - 12.0 - f64::from(qp_offset)) / 6.0)
+}
+
+fn parse_error(reason: &str) -> Error {
+    Error::InvalidStats {line: None, reason: reason.to_owned()}
+}
+
+/// the error of the line at `index` of a stats file.
+fn at_line(error: Error, index: usize) -> Error {
+    match error {
+        Error::InvalidStats {reason, ..} => Error::InvalidStats {line: Some(index + 1), reason},
+        error => error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the start of a stats file written by x264 stable.
+    const STATS: &str = "\
+#options: 64x48 fps=25/1 timebase=1/25 bitdepth=8 cabac=1 ref=3 deblock=1:0:0 analyse=0x3:0x113 me=hex subme=7 psy=1 psy_rd=1.00:0.00 mixed_ref=1 me_range=16 chroma_me=1 trellis=1 8x8dct=1 cqm=0 deadzone=21,11 fast_pskip=1 chroma_qp_offset=-2 threads=1 lookahead_threads=1 sliced_threads=0 nr=0 decimate=1 interlaced=0 bluray_compat=0 constrained_intra=0 bframes=3 b_pyramid=2 b_adapt=1 b_bias=0 direct=1 weightb=1 open_gop=0 weightp=2 keyint=250 keyint_min=25 scenecut=40 intra_refresh=0 rc_lookahead=40 rc=abr mbtree=1 bitrate=100 ratetol=1.0 qcomp=0.60 qpmin=0 qpmax=69 qpstep=4 ip_ratio=1.40 aq=1:1.00
+in:0 out:0 type:I dur:2 cpbdur:2 q:29.01 aq:27.75 tex:2408 mv:365 misc:5883 imb:12 pmb:0 smb:0 d:- ref:;
+in:4 out:1 type:P dur:2 cpbdur:2 q:33.18 aq:28.83 tex:19 mv:114 misc:179 imb:1 pmb:7 smb:4 d:- ref:28 w:7,107,2,6,63,14,63,14 ;
+in:2 out:2 type:B dur:2 cpbdur:2 q:33.18 aq:34.00 tex:61 mv:139 misc:104 imb:0 pmb:5 smb:7 d:- ref:16 ;
+in:1 out:3 type:b dur:2 cpbdur:2 q:33.18 aq:37.00 tex:45 mv:119 misc:108 imb:0 pmb:9 smb:3 d:- ref:14 ;
+in:3 out:4 type:b dur:2 cpbdur:2 q:33.18 aq:38.67 tex:38 mv:67 misc:103 imb:0 pmb:4 smb:8 d:- ref:2 0 ;
+in:8 out:5 type:P dur:2 cpbdur:2 q:29.18 aq:24.83 tex:23 mv:169 misc:232 imb:1 pmb:10 smb:1 d:- ref:40 0 0 0 0 w:6,51,4,6,63,14,63,14 ;
+";
+
+    #[test]
+    fn round_trip() {
+        let stats: StatsFile = STATS.parse().unwrap();
+        assert_eq!(stats.to_string(), STATS);
+        assert_eq!((stats.header.width, stats.header.height), (64, 48));
+        assert_eq!(stats.header.fps(), Some((25, 1)));
+        assert_eq!(stats.header.get("weightp"), Some("2"));
+        assert_eq!(stats.frames.len(), 6);
+
+        let frame = &stats.frames[1];
+        assert_eq!((frame.input, frame.output, frame.frame_type), (4, 1, FrameType::P));
+        assert_eq!(frame.ref_counts, [28]);
+        assert_eq!(
+            frame.weights,
+            Some(WeightStats {luma: (7, 107, 2), chroma: Some((6, [(63, 14), (63, 14)]))}),
+        );
+        assert_eq!(stats.frames[2].frame_type, FrameType::BRef);
+        assert_eq!(stats.frames[4].ref_counts, [2, 0]);
+    }
+
+    #[test]
+    fn line_numbers() {
+        let stats = STATS.replace("tex:61", "tex:x");
+        assert_eq!(
+            stats.parse::<StatsFile>(),
+            Err(Error::InvalidStats {line: Some(4), reason: String::from("invalid tex:x")}),
+        );
+        assert!(matches!(
+            "in:0".parse::<StatsFile>(),
+            Err(Error::InvalidStats {line: Some(1), ..}),
+        ));
+    }
+
+    #[test]
+    fn high_bit_depth_qp() {
+        let stats: StatsFile = STATS.parse().unwrap();
+        let mut high: StatsFile = STATS.replace("bitdepth=8", "bitdepth=10").parse().unwrap();
+        for frame in &mut high.frames {
+            frame.qp += 12.0;
+        }
+        assert_eq!(high.header.qp_offset(), 12.0);
+        let predicted = stats.predicted_bits(30.0);
+        assert!((high.predicted_bits(42.0) - predicted).abs() < 1e-6 * predicted);
+        let qp = stats.qp_for_bitrate(100.0).unwrap();
+        assert_eq!(high.qp_for_bitrate(100.0), Some(qp + 12));
+    }
+
+    #[test]
+    fn mbtree() {
+        let data = [2, 0x01, 0x80, 0xff, 0x00, 3, 0x00, 0x00, 0xfe, 0x40];
+        let frames = MbTreeFrame::parse_all(&data, 2).unwrap();
+        assert_eq!(frames[0].qp_offsets, [1.5, -1.0]);
+        assert_eq!(frames[1].qp_offsets, [0.0, -1.75]);
+        let bytes = frames.iter().flat_map(MbTreeFrame::to_bytes).collect::<Vec<_>>();
+        assert_eq!(bytes, data);
+        assert!(MbTreeFrame::parse_all(&data[..9], 2).is_err());
+    }
+}
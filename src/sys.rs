@@ -91,10 +91,35 @@ pub type X264PictureT = crate::raw::x264_picture_t;
 // X264 TYPE-DEFS
 ///////////////////////////////////////////////////////////////////////////////
 
+/// The `va_list` argument of `pf_log`, as lowered by bindgen for the target.
+#[cfg(all(target_arch = "x86_64", not(windows)))]
+pub type VaListArg = *mut crate::raw::__va_list_tag;
+
+/// The `va_list` argument of `pf_log`, as lowered by bindgen for the target.
+#[cfg(not(all(target_arch = "x86_64", not(windows))))]
+pub type VaListArg = crate::raw::va_list;
+
 ///////////////////////////////////////////////////////////////////////////////
 // X264 CONSTANTS
 ///////////////////////////////////////////////////////////////////////////////
 
+///////////////////////////////////////////////////////////////////////////////
+// X264 VERSION
+///////////////////////////////////////////////////////////////////////////////
+
+/// X264_BUILD, bumped whenever the API or ABI of libx264 changes.
+pub fn build_number() -> u32 {
+    crate::raw::X264_BUILD
+}
+
+/// X264_POINTVER, e.g. "0.157.2980 34c06d1".
+pub fn point_version() -> &'static str {
+    std::ffi::CStr::from_bytes_with_nul(crate::raw::X264_POINTVER)
+        .ok()
+        .and_then(|x| x.to_str().ok())
+        .unwrap_or("")
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 // X264 FUNCTIONS
 ///////////////////////////////////////////////////////////////////////////////
@@ -112,6 +137,11 @@ pub unsafe fn x264_nal_encode(
 }
 
 
+/// fill x264_param_t with default values and do CPU detection
+pub unsafe fn x264_param_default(arg1: *mut X264ParamT) {
+    crate::raw::x264_param_default(arg1)
+}
+
 /// set one parameter by name.
 /// 
 /// returns 0 on success, or returns one of the following errors.
@@ -352,8 +382,10 @@ pub unsafe fn x264_encoder_invalidate_reference(
 }
 
 /// create a new encoder handler, all parameters from x264_param_t are copied
+///
+/// links against `x264_encoder_open_##X264_BUILD` of whichever libx264 is vendored.
 pub unsafe fn x264_encoder_open(arg1: *mut X264ParamT) -> *mut X264T {
-    crate::raw::x264_encoder_open_157(arg1)
+    crate::raw::x264_encoder_open(arg1)
 }
 
 
@@ -0,0 +1,262 @@
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::raw::{c_char, c_int};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::encoder::{Encoder, StreamStats};
+use crate::error::{Error, Result};
+use crate::frame::OwnedEncodedFrame;
+use crate::params::Params;
+use crate::picture::Picture;
+use crate::raw;
+use crate::sys;
+
+///////////////////////////////////////////////////////////////////////////////
+// FRAME SOURCE
+///////////////////////////////////////////////////////////////////////////////
+
+/// Input that can be read again from the start, once per pass.
+///
+/// Every pass must see the same pictures, in the same order.
+pub trait FrameSource {
+    /// go back to the first picture.
+    fn rewind(&mut self) -> Result<()>;
+
+    /// the next picture, `None` at the end of the input.
+    fn next_picture(&mut self) -> Result<Option<Picture<'_>>>;
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// TWO PASS ENCODER
+///////////////////////////////////////////////////////////////////////////////
+
+/// Multi-pass ABR encoding, as with x264CLI's `--pass`.
+///
+/// The first pass only writes the stats file (`rc.b_stat_write`), middle
+/// passes read and rewrite it, and the last pass reads it to distribute the
+/// bitrate. Stats and mbtree files are kept in a temporary directory that is
+/// removed along with the encoder.
+pub struct TwoPassEncoder {
+    params: Params,
+    passes: u32,
+    fast_first_pass: bool,
+    dir: PathBuf,
+}
+
+impl TwoPassEncoder {
+    /// two passes with a fast first pass, in a new directory under the
+    /// system temporary directory.
+    ///
+    /// `params` must use ABR ratecontrol, which is what the stats are for.
+    pub fn new(params: Params) -> Result<Self> {
+        TwoPassEncoder::new_in(params, std::env::temp_dir())
+    }
+
+    /// `new`, in a new directory under `parent`.
+    pub fn new_in(params: Params, parent: impl AsRef<Path>) -> Result<Self> {
+        if params.as_raw().rc.i_rc_method != raw::X264_RC_ABR as c_int {
+            return Err(Error::InvalidParams(String::from("multi-pass requires ABR ratecontrol")));
+        }
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.subsec_nanos())
+            .unwrap_or(0);
+        let dir = parent.as_ref().join(format!(
+            "x264-passes-{}-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+            nanos,
+        ));
+        fs::create_dir_all(&dir).map_err(|e| Error::io(&dir, e))?;
+        Ok(TwoPassEncoder {params, passes: 2, fast_first_pass: true, dir})
+    }
+
+    /// run `passes` passes in total, at least 2. Passes in between the first
+    /// and the last refine the stats of the previous one.
+    pub fn with_passes(mut self, passes: u32) -> Result<Self> {
+        if passes < 2 {
+            return Err(Error::InvalidParams(format!("{} passes, at least 2 are required", passes)));
+        }
+        self.passes = passes;
+        Ok(self)
+    }
+
+    /// whether to disable options generally not useful on the first pass,
+    /// see x264_param_apply_fastfirstpass. Enabled by default.
+    pub fn with_fast_first_pass(mut self, enabled: bool) -> Self {
+        self.fast_first_pass = enabled;
+        self
+    }
+
+    pub fn passes(&self) -> u32 {
+        self.passes
+    }
+
+    /// the stats file, along with the mbtree file next to it.
+    pub fn stats_path(&self) -> PathBuf {
+        self.dir.join("x264.stats")
+    }
+
+    /// run every pass over `source`, handing the frames of the last pass
+    /// to `output`.
+    ///
+    /// the output of the other passes is discarded. Returns the totals of the
+    /// last pass.
+    pub fn encode<S, F>(&mut self, source: &mut S, mut output: F) -> Result<StreamStats>
+    where
+        S: FrameSource,
+        F: FnMut(OwnedEncodedFrame) -> Result<()>,
+    {
+        let mut frames = None;
+        for pass in 1..=self.passes {
+            let mut encoder: Encoder = self.open_pass(pass)?;
+            let last = pass == self.passes;
+            let mut count = 0u64;
+            source.rewind()?;
+            while let Some(pic) = source.next_picture()? {
+                count += 1;
+                if let Some(frame) = encoder.encode(&pic, ())? {
+                    if last {
+                        output(frame.into_owned())?;
+                    }
+                }
+            }
+            if let Some(expected) = frames.filter(|x| *x != count) {
+                return Err(Error::FrameCountMismatch {pass, expected, actual: count});
+            }
+            frames = Some(count);
+            let mut drain = encoder.finish();
+            for frame in &mut drain {
+                let frame = frame?;
+                if last {
+                    output(frame)?;
+                }
+            }
+            if last {
+                return Ok(drain.stats());
+            }
+            // the stats file is only complete once the encoder is closed
+            drop(drain);
+        }
+        unreachable!("at least 2 passes")
+    }
+
+    fn open_pass(&self, pass: u32) -> Result<Encoder> {
+        let stats = self.stats_path();
+        let stats = CString::new(stats.to_string_lossy().into_owned())
+            .map_err(|e| Error::io(&stats, io::Error::new(io::ErrorKind::InvalidInput, e)))?;
+        let mut params = self.params.clone();
+        unsafe {
+            // both are copied by x264_encoder_open
+            let param = params.as_raw_mut();
+            param.rc.b_stat_write = (pass < self.passes) as c_int;
+            param.rc.b_stat_read = (pass > 1) as c_int;
+            param.rc.psz_stat_out = stats.as_ptr() as *mut c_char;
+            param.rc.psz_stat_in = stats.as_ptr() as *mut c_char;
+            if pass == 1 && self.fast_first_pass {
+                sys::x264_param_apply_fastfirstpass(param);
+            }
+        }
+        Encoder::open(&params)
+    }
+}
+
+impl Drop for TwoPassEncoder {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colorspace::Colorspace;
+    use crate::params::RateControl;
+
+    /// `counts[n]` flat pictures on the nth pass.
+    struct Source {
+        counts: Vec<u64>,
+        pass: usize,
+        index: u64,
+    }
+
+    impl Source {
+        fn new(counts: Vec<u64>) -> Self {
+            Source {counts, pass: 0, index: 0}
+        }
+    }
+
+    impl FrameSource for Source {
+        fn rewind(&mut self) -> Result<()> {
+            self.pass += 1;
+            self.index = 0;
+            Ok(())
+        }
+
+        fn next_picture(&mut self) -> Result<Option<Picture<'_>>> {
+            if self.index == self.counts[self.pass - 1] {
+                return Ok(None);
+            }
+            let mut pic = Picture::new(Colorspace::I420, 64, 48)?;
+            pic.set_pts(self.index as i64);
+            self.index += 1;
+            Ok(Some(pic))
+        }
+    }
+
+    fn abr() -> Params {
+        Params::builder()
+            .size(64, 48)
+            .rate_control(RateControl::Abr(100))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn encode() {
+        let mut encoder = TwoPassEncoder::new(abr()).unwrap();
+        let mut frames = 0;
+        let stats = encoder.encode(&mut Source::new(vec![10, 10]), |_| {
+            frames += 1;
+            Ok(())
+        });
+        assert!(stats.is_ok());
+        assert_eq!(frames, 10);
+        assert!(encoder.stats_path().exists());
+    }
+
+    #[test]
+    fn frame_count_mismatch() {
+        let mut encoder = TwoPassEncoder::new(abr()).unwrap().with_passes(3).unwrap();
+        let result = encoder.encode(&mut Source::new(vec![10, 10, 9]), |_| Ok(()));
+        assert_eq!(
+            result.err(),
+            Some(Error::FrameCountMismatch {pass: 3, expected: 10, actual: 9}),
+        );
+    }
+
+    #[test]
+    fn passes() {
+        assert!(TwoPassEncoder::new(abr()).unwrap().with_passes(1).is_err());
+        assert_eq!(TwoPassEncoder::new(abr()).unwrap().with_passes(3).unwrap().passes(), 3);
+    }
+
+    #[test]
+    fn ratecontrol() {
+        let params = Params::builder().size(64, 48).build().unwrap();
+        assert!(matches!(TwoPassEncoder::new(params), Err(Error::InvalidParams(_))));
+    }
+
+    #[test]
+    fn temp_dir_removed() {
+        let encoder = TwoPassEncoder::new(abr()).unwrap();
+        let dir = encoder.stats_path().parent().unwrap().to_owned();
+        assert!(dir.is_dir());
+        drop(encoder);
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,160 @@
+use std::os::raw::c_int;
+
+use crate::names::{self, impl_names};
+use crate::sys::X264ParamT;
+
+///////////////////////////////////////////////////////////////////////////////
+// VUI ENUMS
+///////////////////////////////////////////////////////////////////////////////
+
+/// Overscan info, see H.264 Annex E.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Overscan {
+    Undefined = 0,
+    /// the picture is suitable for display with no overscan.
+    Show = 1,
+    /// the picture may be cropped by overscan.
+    Crop = 2,
+}
+
+impl_names!(Overscan, names::overscan_names, "overscan", [Undefined, Show, Crop]);
+
+/// Video format, see H.264 Annex E, Table E-2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VideoFormat {
+    Component = 0,
+    Pal = 1,
+    Ntsc = 2,
+    Secam = 3,
+    Mac = 4,
+    Undefined = 5,
+}
+
+impl_names!(VideoFormat, names::vidformat_names, "videoformat", [
+    Component, Pal, Ntsc, Secam, Mac, Undefined,
+]);
+
+/// Colour primaries, see H.264 Annex E, Table E-3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorPrimaries {
+    Bt709 = 1,
+    Undefined = 2,
+    Bt470M = 4,
+    Bt470BG = 5,
+    Smpte170M = 6,
+    Smpte240M = 7,
+    Film = 8,
+    Bt2020 = 9,
+    Smpte428 = 10,
+    Smpte431 = 11,
+    Smpte432 = 12,
+}
+
+impl_names!(ColorPrimaries, names::colorprim_names, "colorprim", [
+    Bt709, Undefined, Bt470M, Bt470BG, Smpte170M, Smpte240M, Film, Bt2020, Smpte428, Smpte431,
+    Smpte432,
+]);
+
+/// Transfer characteristics, see H.264 Annex E, Table E-4.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransferCharacteristics {
+    Bt709 = 1,
+    Undefined = 2,
+    Bt470M = 4,
+    Bt470BG = 5,
+    Smpte170M = 6,
+    Smpte240M = 7,
+    Linear = 8,
+    Log100 = 9,
+    Log316 = 10,
+    Iec61966_2_4 = 11,
+    Bt1361E = 12,
+    Iec61966_2_1 = 13,
+    Bt2020_10 = 14,
+    Bt2020_12 = 15,
+    /// PQ, used by HDR10.
+    Smpte2084 = 16,
+    Smpte428 = 17,
+    /// HLG.
+    AribStdB67 = 18,
+}
+
+impl_names!(TransferCharacteristics, names::transfer_names, "transfer", [
+    Bt709, Undefined, Bt470M, Bt470BG, Smpte170M, Smpte240M, Linear, Log100, Log316,
+    Iec61966_2_4, Bt1361E, Iec61966_2_1, Bt2020_10, Bt2020_12, Smpte2084, Smpte428, AribStdB67,
+]);
+
+/// Matrix coefficients, see H.264 Annex E, Table E-5.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MatrixCoefficients {
+    Gbr = 0,
+    Bt709 = 1,
+    Undefined = 2,
+    Fcc = 4,
+    Bt470BG = 5,
+    Smpte170M = 6,
+    Smpte240M = 7,
+    YCgCo = 8,
+    Bt2020NC = 9,
+    Bt2020C = 10,
+    Smpte2085 = 11,
+    ChromaDerivedNC = 12,
+    ChromaDerivedC = 13,
+    ICtCp = 14,
+}
+
+impl_names!(MatrixCoefficients, names::colmatrix_names, "colormatrix", [
+    Gbr, Bt709, Undefined, Fcc, Bt470BG, Smpte170M, Smpte240M, YCgCo, Bt2020NC, Bt2020C,
+    Smpte2085, ChromaDerivedNC, ChromaDerivedC, ICtCp,
+]);
+
+///////////////////////////////////////////////////////////////////////////////
+// VUI
+///////////////////////////////////////////////////////////////////////////////
+
+/// Typed counterpart of the `vui` sub-struct of `X264ParamT`.
+///
+/// The default matches x264's own defaults, i.e. everything unspecified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vui {
+    /// sample aspect ratio as (width, height), reduced by x264.
+    pub sar: Option<(u32, u32)>,
+    pub overscan: Overscan,
+    pub video_format: VideoFormat,
+    pub full_range: bool,
+    pub color_primaries: ColorPrimaries,
+    pub transfer: TransferCharacteristics,
+    pub color_matrix: MatrixCoefficients,
+    /// chroma sample location, both top & bottom, in `0..=5`.
+    pub chroma_loc: u8,
+}
+
+impl Default for Vui {
+    fn default() -> Self {
+        Vui {
+            sar: None,
+            overscan: Overscan::Undefined,
+            video_format: VideoFormat::Undefined,
+            full_range: false,
+            color_primaries: ColorPrimaries::Undefined,
+            transfer: TransferCharacteristics::Undefined,
+            color_matrix: MatrixCoefficients::Undefined,
+            chroma_loc: 0,
+        }
+    }
+}
+
+impl Vui {
+    pub(crate) fn apply(&self, param: &mut X264ParamT) {
+        let (sar_width, sar_height) = self.sar.unwrap_or((0, 0));
+        param.vui.i_sar_width = sar_width as c_int;
+        param.vui.i_sar_height = sar_height as c_int;
+        param.vui.i_overscan = self.overscan as c_int;
+        param.vui.i_vidformat = self.video_format as c_int;
+        param.vui.b_fullrange = self.full_range as c_int;
+        param.vui.i_colorprim = self.color_primaries as c_int;
+        param.vui.i_transfer = self.transfer as c_int;
+        param.vui.i_colmatrix = self.color_matrix as c_int;
+        param.vui.i_chroma_loc = self.chroma_loc as c_int;
+    }
+}
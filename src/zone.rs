@@ -0,0 +1,210 @@
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+
+///////////////////////////////////////////////////////////////////////////////
+// ZONE
+///////////////////////////////////////////////////////////////////////////////
+
+/// How a zone overrides ratecontrol.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ZoneKind {
+    /// force the quantizer of P-frames, the others following the usual ratios.
+    Qp(u8),
+    /// multiply the bitrate given to the zone, e.g. 0.5 for credits.
+    BitrateFactor(f32),
+}
+
+/// A range of frames overriding ratecontrol or other options, see
+/// `ParamsBuilder::zones`.
+///
+/// Other options are changed through `x264_encoder_reconfig` when the zone
+/// starts, see it for which options can be changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zone {
+    /// frame numbers in display order.
+    pub frames: Range<u32>,
+    pub kind: ZoneKind,
+    /// options as accepted by x264_param_parse, e.g. `("crf", Some("20"))`.
+    pub params_override: Vec<(String, Option<String>)>,
+}
+
+impl Zone {
+    pub fn new(frames: Range<u32>, kind: ZoneKind) -> Self {
+        Zone {frames, kind, params_override: Vec::new()}
+    }
+
+    /// override one more option, see `ParamsBuilder::option`.
+    pub fn with_option(mut self, name: &str, value: Option<&str>) -> Self {
+        self.params_override.push((name.to_owned(), value.map(ToOwned::to_owned)));
+        self
+    }
+
+    fn check(&self, frame_total: Option<u32>) -> Result<()> {
+        if self.frames.is_empty() {
+            return Err(Error::InvalidParams(format!("empty zone {:?}", self.frames)));
+        }
+        if self.frames.end > i32::MAX as u32 {
+            return Err(Error::InvalidParams(format!("zone {:?} is out of range", self.frames)));
+        }
+        if let Some(total) = frame_total {
+            if self.frames.end > total {
+                return Err(Error::InvalidParams(format!(
+                    "zone {:?} ends past the last of {} frames",
+                    self.frames, total,
+                )));
+            }
+        }
+        if let ZoneKind::BitrateFactor(factor) = self.kind {
+            if !(factor > 0.0 && factor.is_finite()) {
+                return Err(Error::InvalidParams(format!(
+                    "invalid zone bitrate factor {}",
+                    factor,
+                )));
+            }
+        }
+        for (name, value) in &self.params_override {
+            // neither may contain the separators of the zones syntax
+            let bad = |x: &str| x.is_empty() || x.contains([',', '/', '=', '\0']);
+            if bad(name) || value.as_deref().is_some_and(|x| x.contains([',', '/', '\0'])) {
+                return Err(Error::BadParamValue {name: name.clone(), value: value.clone()});
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The syntax of x264's `--zones`, e.g. "0,99,q=20,me=umh", with an
+/// inclusive end frame.
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{}", self.frames.start, self.frames.end.saturating_sub(1))?;
+        match self.kind {
+            ZoneKind::Qp(qp) => write!(f, ",q={}", qp)?,
+            ZoneKind::BitrateFactor(factor) => write!(f, ",b={}", factor)?,
+        }
+        for (name, value) in &self.params_override {
+            match value {
+                Some(value) => write!(f, ",{}={}", name, value)?,
+                None => write!(f, ",{}", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses a single zone, like x264's parse_zone: the frame range must be
+/// followed by either `q=` or `b=`.
+impl FromStr for Zone {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bad_value = || Error::BadParamValue {
+            name: "zones".to_owned(),
+            value: Some(s.to_owned()),
+        };
+        let mut tokens = s.split(',');
+        let mut frame = || -> Result<u32> {
+            tokens.next().and_then(|x| x.trim().parse().ok()).ok_or_else(bad_value)
+        };
+        let (start, end) = (frame()?, frame()?);
+        if end < start {
+            return Err(bad_value());
+        }
+        let kind = match tokens.next().and_then(|x| x.split_once('=')) {
+            Some(("q", value)) => ZoneKind::Qp(value.parse().map_err(|_| bad_value())?),
+            Some(("b", value)) => ZoneKind::BitrateFactor(value.parse().map_err(|_| bad_value())?),
+            _ => return Err(bad_value()),
+        };
+        let params_override = tokens
+            .filter(|x| !x.is_empty())
+            .map(|token| match token.split_once('=') {
+                Some((name, value)) => (name.to_owned(), Some(value.to_owned())),
+                None => (token.to_owned(), None),
+            })
+            .collect();
+        let end = end.checked_add(1).ok_or_else(bad_value)?;
+        Ok(Zone {frames: start..end, kind, params_override})
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ZONE LISTS
+///////////////////////////////////////////////////////////////////////////////
+
+/// Parses a list of zones separated by '/', as in `rc.psz_zones`.
+pub fn parse(zones: &str) -> Result<Vec<Zone>> {
+    zones.split('/').map(str::parse).collect()
+}
+
+/// Formats zones as `rc.psz_zones`, the inverse of `parse`.
+pub fn format(zones: &[Zone]) -> String {
+    zones.iter().map(Zone::to_string).collect::<Vec<_>>().join("/")
+}
+
+/// Checks that every zone is valid and within `frame_total` if known.
+///
+/// x264 lets later zones take precedence where they overlap, which is
+/// rejected here since it is most likely a mistake.
+pub fn validate(zones: &[Zone], frame_total: Option<u32>) -> Result<()> {
+    for zone in zones {
+        zone.check(frame_total)?;
+    }
+    let mut sorted = zones.iter().map(|x| &x.frames).collect::<Vec<_>>();
+    sorted.sort_by_key(|x| x.start);
+    for pair in sorted.windows(2) {
+        if pair[0].end > pair[1].start {
+            return Err(Error::InvalidParams(format!(
+                "zones {:?} and {:?} overlap",
+                pair[0], pair[1],
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let zone: Zone = "0,99,q=20,me=umh".parse().unwrap();
+        assert_eq!(zone.frames, 0..100);
+        assert_eq!(zone.kind, ZoneKind::Qp(20));
+        assert_eq!(zone.params_override, [("me".to_owned(), Some("umh".to_owned()))]);
+        assert_eq!(zone.to_string(), "0,99,q=20,me=umh");
+
+        let zones = parse("0,99,q=20,me=umh/200,299,b=0.5").unwrap();
+        assert_eq!(zones[1], Zone::new(200..300, ZoneKind::BitrateFactor(0.5)));
+        assert_eq!(format(&zones), "0,99,q=20,me=umh/200,299,b=0.5");
+
+        assert!("0,9,crf=20".parse::<Zone>().is_err());
+        assert!("0,9".parse::<Zone>().is_err());
+        assert!("0,9,q".parse::<Zone>().is_err());
+        assert_eq!("0,9,b=1,crf=20".parse::<Zone>().unwrap().to_string(), "0,9,b=1,crf=20");
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert!("0,4294967295,q=20".parse::<Zone>().is_err());
+        assert!("10,9,q=20".parse::<Zone>().is_err());
+    }
+
+    #[test]
+    fn overlap() {
+        let zones = parse("0,99,q=20/50,149,b=0.5").unwrap();
+        assert!(validate(&zones, None).is_err());
+        let zones = parse("0,99,q=20/100,149,b=0.5").unwrap();
+        assert!(validate(&zones, None).is_ok());
+    }
+
+    #[test]
+    fn frame_total() {
+        let zones = parse("0,99,q=20").unwrap();
+        assert!(validate(&zones, Some(100)).is_ok());
+        assert!(validate(&zones, Some(99)).is_err());
+    }
+}